@@ -0,0 +1,7 @@
+// Entry point of the denoising Web Worker, built by trunk as a separate
+// wasm module (see the `data-type="worker"` link in index.html).
+
+fn main() {
+    console_error_panic_hook::set_once();
+    tgv_web::worker::run();
+}
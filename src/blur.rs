@@ -0,0 +1,58 @@
+// Blur removed together with the noise, see `tgv::tgv_deblur`. The kernel is built
+// per job, so that it can be shrunk along with the image for automatic updates.
+
+use ndarray::{Array3, Axis};
+use tgv::Kernel;
+
+use crate::{error::AppError, shrink};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlurKind {
+    None,
+    Gaussian,
+    Motion,
+    // Point spread function uploaded as an image
+    Psf,
+}
+
+impl BlurKind {
+    pub const ALL: [BlurKind; 4] = [BlurKind::None, BlurKind::Gaussian, BlurKind::Motion, BlurKind::Psf];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            BlurKind::None => "None",
+            BlurKind::Gaussian => "Gaussian",
+            BlurKind::Motion => "Motion",
+            BlurKind::Psf => "Uploaded PSF",
+        }
+    }
+}
+
+// State of the blur controls. Lengths are in pixels of the full resolution image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Blur {
+    pub kind: BlurKind,
+    pub sigma: f32,
+    pub length: f32,
+    // Degrees counterclockwise from the x axis
+    pub angle: f32,
+}
+
+impl Blur {
+    // Kernel for the image shrunk by `factor`, or None without blur. `psf` is the
+    // uploaded (height, width, 1) point spread function
+    pub fn kernel(&self, psf: Option<&Array3<f32>>, factor: usize) -> Result<Option<Kernel>, AppError> {
+        let factor_f = factor as f32;
+        let kernel = match self.kind {
+            BlurKind::None => return Ok(None),
+            BlurKind::Gaussian => Kernel::gaussian(self.sigma / factor_f),
+            BlurKind::Motion => Kernel::motion(self.length / factor_f, self.angle),
+            // Averaging blocks of the PSF approximates the blur of the averaged image
+            BlurKind::Psf => {
+                let psf = psf.ok_or(AppError::Solver("no point spread function uploaded".to_string()))?;
+                Kernel::new(&shrink(&psf.view(), factor).index_axis(Axis(2), 0))
+            }
+        };
+        Ok(Some(kernel?))
+    }
+}
@@ -0,0 +1,217 @@
+// Viewer comparing the original and the denoised image: a draggable before/after
+// split, the two side by side, or their difference. Zoom and pan are shared by all
+// views, so both images always show the same pixels.
+
+use leptos::{ev, prelude::*};
+use ndarray::{Array3, ArrayView3, Zip};
+use web_sys::{wasm_bindgen::JsCast, Element};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompareMode {
+    Split,
+    SideBySide,
+    Difference,
+}
+
+impl CompareMode {
+    pub const ALL: [CompareMode; 3] = [CompareMode::Split, CompareMode::SideBySide, CompareMode::Difference];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            CompareMode::Split => "Before / after",
+            CompareMode::SideBySide => "Side by side",
+            CompareMode::Difference => "Difference",
+        }
+    }
+}
+
+// Absolute difference of two images of the same shape, per channel
+pub fn difference(original: &ArrayView3<f32>, denoised: &ArrayView3<f32>) -> Array3<f32> {
+    Zip::from(original).and(denoised).map_collect(|&a, &b| (a - b).abs())
+}
+
+// Zoom limits, as screen pixels per image pixel
+const MIN_ZOOM: f32 = 0.05;
+const MAX_ZOOM: f32 = 32.0;
+// Zoom factor of one wheel step
+const WHEEL_ZOOM: f32 = 1.2;
+const VIEWPORT_HEIGHT: &str = "480px";
+
+// Position of the images in the viewports: image pixel (x, y) is drawn at
+// (offset_x + zoom * x, offset_y + zoom * y)
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Transform {
+    zoom: f32,
+    offset_x: f32,
+    offset_y: f32,
+}
+
+impl Transform {
+    const IDENTITY: Transform = Transform { zoom: 1.0, offset_x: 0.0, offset_y: 0.0 };
+
+    // Zooms by `factor`, keeping the image pixel under (x, y) in place
+    fn zoom_at(self, x: f32, y: f32, factor: f32) -> Transform {
+        let zoom = (self.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        let ratio = zoom / self.zoom;
+        Transform { zoom, offset_x: x - (x - self.offset_x) * ratio, offset_y: y - (y - self.offset_y) * ratio }
+    }
+
+    fn css(self) -> String {
+        format!("translate({}px, {}px) scale({})", self.offset_x, self.offset_y, self.zoom)
+    }
+}
+
+// What the pointer is dragging in a viewport
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Drag {
+    // The image, from where the pointer went down
+    Pan { x: f32, y: f32, start: Transform },
+    // The before/after divider
+    Divider,
+}
+
+// Pointer position relative to the top left corner of the element the handler is attached to,
+// and that element's width
+fn local_position(ev: &web_sys::MouseEvent) -> Option<(f32, f32, f32)> {
+    let element = ev.current_target()?.dyn_into::<Element>().ok()?;
+    let rect = element.get_bounding_client_rect();
+    Some((ev.client_x() as f32 - rect.left() as f32, ev.client_y() as f32 - rect.top() as f32, rect.width() as f32))
+}
+
+// One image layer of a viewport, optionally clipped to the part right of `clip_left`
+// percent of the viewport
+fn layer(src: Signal<String>, alt: &'static str, width: Signal<Option<usize>>, transform: ReadSignal<Transform>, clip_left: Option<ReadSignal<f32>>) -> impl IntoView {
+    let clip = move || clip_left.map(|split| format!("inset(0 0 0 {}%)", 100.0 * split.get())).unwrap_or_default();
+    view! {
+        <div style="position: absolute; inset: 0;" style:clip-path=clip>
+            <img
+                src=src
+                alt=alt
+                width=width
+                draggable="false"
+                style="position: absolute; left: 0; top: 0; max-width: none; transform-origin: 0 0; image-rendering: pixelated;"
+                style:transform=move || transform.get().css()
+            />
+        </div>
+    }
+}
+
+// A window onto the images, with wheel zoom, drag to pan and, if `split` is given,
+// a before/after divider
+#[component]
+fn Viewport(
+    transform: ReadSignal<Transform>,
+    set_transform: WriteSignal<Transform>,
+    #[prop(optional)] split: Option<(ReadSignal<f32>, WriteSignal<f32>)>,
+    children: Children,
+) -> impl IntoView {
+    let drag = StoredValue::new(None::<Drag>);
+
+    let on_wheel = move |ev: ev::WheelEvent| {
+        ev.prevent_default();
+        if let Some((x, y, _)) = local_position(&ev) {
+            let factor = if ev.delta_y() < 0.0 { WHEEL_ZOOM } else { 1.0 / WHEEL_ZOOM };
+            set_transform.update(|transform| *transform = transform.zoom_at(x, y, factor));
+        }
+    };
+    let on_pointer_down = move |ev: ev::PointerEvent| {
+        let Some((x, y, _)) = local_position(&ev) else { return };
+        let on_divider = ev.target()
+            .and_then(|target| target.dyn_into::<Element>().ok())
+            .is_some_and(|target| target.class_list().contains("split-handle"));
+        drag.set_value(Some(if on_divider { Drag::Divider } else { Drag::Pan { x, y, start: transform.get_untracked() } }));
+        // Keep receiving the moves when the pointer leaves the viewport
+        if let Some(element) = ev.current_target().and_then(|target| target.dyn_into::<Element>().ok()) {
+            let _ = element.set_pointer_capture(ev.pointer_id());
+        }
+    };
+    let on_pointer_move = move |ev: ev::PointerEvent| {
+        let Some((x, y, width)) = local_position(&ev) else { return };
+        match drag.get_value() {
+            Some(Drag::Pan { x: x0, y: y0, start }) => {
+                set_transform.set(Transform { offset_x: start.offset_x + x - x0, offset_y: start.offset_y + y - y0, ..start });
+            }
+            Some(Drag::Divider) => {
+                if let Some((_, set_split)) = split {
+                    set_split.set((x / width).clamp(0.0, 1.0));
+                }
+            }
+            None => {}
+        }
+    };
+    let on_pointer_up = move |_: ev::PointerEvent| drag.set_value(None);
+
+    view! {
+        <div
+            class="viewport"
+            style="position: relative; flex: 1; overflow: hidden; touch-action: none; cursor: grab; background: #222;"
+            style:height=VIEWPORT_HEIGHT
+            on:wheel=on_wheel
+            on:pointerdown=on_pointer_down
+            on:pointermove=on_pointer_move
+            on:pointerup=on_pointer_up
+            on:pointercancel=on_pointer_up
+        >
+            {children()}
+            {split.map(|(split, _)| view! {
+                <div
+                    class="split-handle"
+                    style="position: absolute; top: 0; bottom: 0; width: 4px; margin-left: -2px; background: white; cursor: ew-resize;"
+                    style:left=move || format!("{}%", 100.0 * split.get())
+                ></div>
+            })}
+        </div>
+    }
+}
+
+// Compares `original` and `processed`, both data URLs. `width` is the width of the
+// original image, `processed` is stretched to it if it was denoised at a lower
+// resolution. `difference` may be empty while it is not available
+#[component]
+pub fn CompareViewer(
+    original: Signal<String>,
+    processed: Signal<String>,
+    difference: Signal<String>,
+    width: Signal<Option<usize>>,
+    mode: ReadSignal<CompareMode>,
+    set_mode: WriteSignal<CompareMode>,
+) -> impl IntoView {
+    let (transform, set_transform) = signal(Transform::IDENTITY);
+    let (split, set_split) = signal(0.5f32);
+
+    let views = move || match mode.get() {
+        CompareMode::Split => view! {
+            <Viewport transform=transform set_transform=set_transform split=(split, set_split)>
+                {layer(original, "Original Image", width, transform, None)}
+                {layer(processed, "Denoised Image", width, transform, Some(split))}
+            </Viewport>
+        }.into_any(),
+        CompareMode::SideBySide => view! {
+            <Viewport transform=transform set_transform=set_transform>
+                {layer(original, "Original Image", width, transform, None)}
+            </Viewport>
+            <Viewport transform=transform set_transform=set_transform>
+                {layer(processed, "Denoised Image", width, transform, None)}
+            </Viewport>
+        }.into_any(),
+        CompareMode::Difference => view! {
+            <Viewport transform=transform set_transform=set_transform>
+                {layer(difference, "Difference", width, transform, None)}
+            </Viewport>
+        }.into_any(),
+    };
+
+    view! {
+        <div class="compare-viewer">
+            <div style="display: flex; align-items: center; gap: 16px;">
+                <crate::EnumSelect label="view" options=&CompareMode::ALL value=mode value_setter=set_mode option_label=CompareMode::label />
+                <span>{move || format!("zoom {:.0}%", 100.0 * transform.get().zoom)}</span>
+                <button on:click=move |_| set_transform.set(Transform::IDENTITY)>"Reset view"</button>
+                <Show when=move || mode.get() == CompareMode::Split>
+                    <span>"Drag the divider: original on the left, denoised on the right"</span>
+                </Show>
+            </div>
+            <div style="display: flex; gap: 4px;">{views}</div>
+        </div>
+    }
+}
@@ -0,0 +1,79 @@
+// Main thread handle to the denoising worker.
+//
+// A running job blocks the worker's event loop, so it cannot be told to stop. To
+// cancel, drop the Denoiser, which terminates the worker, and spawn a new one.
+
+use std::{cell::RefCell, rc::Rc};
+
+use tgv_web::protocol::{self, Request, Response};
+use web_sys::{
+    js_sys,
+    wasm_bindgen::{closure::Closure, JsCast, JsValue},
+    MessageEvent, Worker,
+};
+
+use crate::error::AppError;
+
+// Loader generated by trunk for the `worker` binary (see index.html)
+const WORKER_SCRIPT: &str = "./worker_loader.js";
+
+struct State {
+    ready: bool,
+    // Requests posted before the worker finished loading its wasm module
+    queued: Vec<(JsValue, js_sys::Array)>,
+}
+
+pub struct Denoiser {
+    worker: Worker,
+    state: Rc<RefCell<State>>,
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+}
+
+impl Denoiser {
+    // Spawns the worker. `on_response` is called on the main thread for every
+    // message the worker sends back, together with its pixel buffer (if any).
+    pub fn spawn(mut on_response: impl FnMut(Response, Option<Vec<f32>>) + 'static) -> Result<Self, AppError> {
+        let worker = Worker::new(WORKER_SCRIPT)
+            .map_err(|e| AppError::Worker(format!("failed to start: {:?}", e)))?;
+        let state = Rc::new(RefCell::new(State { ready: false, queued: Vec::new() }));
+
+        let handler_worker = worker.clone();
+        let handler_state = state.clone();
+        let on_message = Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+            match protocol::decode::<Response>(&event.data()) {
+                Ok((Response::Ready, _)) => {
+                    let mut state = handler_state.borrow_mut();
+                    state.ready = true;
+                    for (message, transfer) in state.queued.drain(..) {
+                        if let Err(e) = handler_worker.post_message_with_transfer(&message, &transfer) {
+                            web_sys::console::error_1(&e);
+                        }
+                    }
+                }
+                Ok((response, pixels)) => on_response(response, pixels),
+                Err(e) => web_sys::console::error_1(&e.into()),
+            }
+        });
+        worker.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+        Ok(Denoiser { worker, state, _on_message: on_message })
+    }
+
+    pub fn post(&self, request: &Request, pixels: Option<&[f32]>) -> Result<(), AppError> {
+        let (message, transfer) = protocol::encode(request, pixels).map_err(AppError::Worker)?;
+        let mut state = self.state.borrow_mut();
+        if state.ready {
+            self.worker.post_message_with_transfer(&message, &transfer)
+                .map_err(|e| AppError::Worker(format!("failed to send the image: {:?}", e)))
+        } else {
+            state.queued.push((message, transfer));
+            Ok(())
+        }
+    }
+}
+
+impl Drop for Denoiser {
+    fn drop(&mut self) {
+        self.worker.terminate();
+    }
+}
@@ -0,0 +1,70 @@
+// Errors shown to the user in the error banner of the App.
+
+use std::fmt;
+
+use image::ImageError;
+
+// Largest image accepted for denoising, in pixels. The solver keeps about 30 floats
+// per pixel and channel around, so larger images would exhaust the wasm memory
+pub const MAX_PIXELS: u64 = 16_000_000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AppError {
+    // No file has been selected yet
+    NoFile,
+    // The browser could not read the selected file
+    FileRead(String),
+    // The file is not in an image format we can decode
+    UnsupportedFormat(String),
+    // The file looks like a supported image but could not be decoded
+    Decode(String),
+    TooLarge { width: u32, height: u32 },
+    // The inpainting mask does not fit the image
+    InvalidMask(String),
+    // Invalid parameters, or the solver failed in the worker
+    Solver(String),
+    // The denoising worker could not be started or talked to
+    Worker(String),
+    // The result could not be encoded for display or download
+    Encode(String),
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::NoFile => write!(f, "Please select an image file first"),
+            AppError::FileRead(e) => write!(f, "Failed to read file: {}", e),
+            AppError::UnsupportedFormat(e) => write!(f, "Unsupported image format: {}", e),
+            AppError::Decode(e) => write!(f, "Failed to decode image: {}", e),
+            AppError::TooLarge { width, height } => write!(
+                f,
+                "Image is too large: {} x {} pixels, at most {} megapixels are supported",
+                width,
+                height,
+                MAX_PIXELS / 1_000_000
+            ),
+            AppError::InvalidMask(e) => write!(f, "Invalid mask: {}", e),
+            AppError::Solver(e) => write!(f, "Denoising failed: {}", e),
+            AppError::Worker(e) => write!(f, "Denoising worker error: {}", e),
+            AppError::Encode(e) => write!(f, "Failed to encode image: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl From<ImageError> for AppError {
+    fn from(e: ImageError) -> Self {
+        match e {
+            ImageError::Unsupported(e) => AppError::UnsupportedFormat(e.to_string()),
+            ImageError::IoError(e) => AppError::FileRead(e.to_string()),
+            e => AppError::Decode(e.to_string()),
+        }
+    }
+}
+
+impl From<tgv::TgvError> for AppError {
+    fn from(e: tgv::TgvError) -> Self {
+        AppError::Solver(e.to_string())
+    }
+}
@@ -0,0 +1,109 @@
+// Saving the denoised image: encoding it in the format picked by the user and handing
+// it to the browser as a file download.
+
+use std::io::Cursor;
+
+use image::ImageFormat;
+use ndarray::Array3;
+use tgv::{array_to_image, BitDepth, DataTerm, Regularization, TgvParams};
+use web_sys::{js_sys, Blob, BlobPropertyBag, HtmlAnchorElement, Url};
+
+use crate::error::AppError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Png,
+    Png16,
+    Tiff,
+    WebP,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 4] = [ExportFormat::Png, ExportFormat::Png16, ExportFormat::Tiff, ExportFormat::WebP];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ExportFormat::Png => "PNG",
+            ExportFormat::Png16 => "PNG (16-bit)",
+            ExportFormat::Tiff => "TIFF (input bit depth)",
+            ExportFormat::WebP => "WebP (lossless)",
+        }
+    }
+
+    fn image_format(self) -> ImageFormat {
+        match self {
+            ExportFormat::Png | ExportFormat::Png16 => ImageFormat::Png,
+            ExportFormat::Tiff => ImageFormat::Tiff,
+            // The image crate only writes lossless WebP
+            ExportFormat::WebP => ImageFormat::WebP,
+        }
+    }
+
+    fn bit_depth(self, input_depth: BitDepth) -> BitDepth {
+        match self {
+            ExportFormat::Png | ExportFormat::WebP => BitDepth::Eight,
+            ExportFormat::Png16 => BitDepth::Sixteen,
+            // TIFF stores every depth
+            ExportFormat::Tiff => input_depth,
+        }
+    }
+
+    // Encodes a (height, width, 3) image on the 0..255 scale, that was decoded from
+    // an image stored at `input_depth`
+    pub fn encode(self, img: &Array3<f32>, input_depth: BitDepth) -> Result<Vec<u8>, AppError> {
+        let img = array_to_image(&img.view(), self.bit_depth(input_depth));
+        let mut bytes = Vec::new();
+        img.write_to(&mut Cursor::new(&mut bytes), self.image_format())
+            .map_err(|e| AppError::Encode(format!("{}: {}", self.label(), e)))?;
+        Ok(bytes)
+    }
+
+    // e.g. "photo_tgv_lambda1.649_alpha0-2_alpha1-1_300iter.png" for "photo.jpg", or
+    // "photo_tv-l1_..." with the TV regularizer and the L1 data term
+    pub fn file_name(self, source_name: &str, params: &TgvParams) -> String {
+        let stem = source_name.rsplit_once('.').map_or(source_name, |(stem, _)| stem);
+        let stem = if stem.is_empty() { "image" } else { stem };
+        let regularizer = match params.regularization {
+            Regularization::Tv => "tv",
+            Regularization::HuberTv => "hubertv",
+            Regularization::Tgv => "tgv",
+        };
+        let data_term = match params.data_term {
+            DataTerm::L2 => "",
+            DataTerm::L1 => "-l1",
+            DataTerm::Kl => "-kl",
+        };
+        format!(
+            "{}_{}{}_lambda{:.3}_alpha0-{}_alpha1-{}_{}iter.{}",
+            stem,
+            regularizer,
+            data_term,
+            params.lambda,
+            params.alpha0,
+            params.alpha1,
+            params.n_iter,
+            self.image_format().extensions_str()[0],
+        )
+    }
+}
+
+// Saves `bytes` as `file_name` through the (hidden) `anchor`
+pub fn download(anchor: &HtmlAnchorElement, bytes: &[u8], format: ExportFormat, file_name: &str) -> Result<(), AppError> {
+    let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(bytes));
+    let options = BlobPropertyBag::new();
+    options.set_type(format.image_format().to_mime_type());
+    let blob = Blob::new_with_u8_array_sequence_and_options(&parts, &options)
+        .map_err(|e| AppError::Encode(format!("failed to create download: {:?}", e)))?;
+    let url = Url::create_object_url_with_blob(&blob)
+        .map_err(|e| AppError::Encode(format!("failed to create download: {:?}", e)))?;
+
+    // Release the previous download, revoking right after the click could cancel it
+    let previous = anchor.href();
+    if previous.starts_with("blob:") {
+        let _ = Url::revoke_object_url(&previous);
+    }
+    anchor.set_href(&url);
+    anchor.set_download(file_name);
+    anchor.click();
+    Ok(())
+}
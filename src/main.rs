@@ -1,193 +1,684 @@
-mod tgv;
-use leptos::{html::Input, logging::log, prelude::*, task::spawn_local};
-use console_error_panic_hook;
-use web_sys::{js_sys, wasm_bindgen::JsCast, HtmlInputElement};
-use image::{DynamicImage, ImageBuffer, ImageFormat, RgbImage, GrayImage};
+mod blur;
+mod compare;
+mod denoiser;
+mod error;
+mod export;
+mod mask;
+use blur::{Blur, BlurKind};
+use compare::{difference, CompareMode, CompareViewer};
+use denoiser::Denoiser;
+use error::{AppError, MAX_PIXELS};
+use export::{download, ExportFormat};
+use mask::MaskPainter;
+use leptos::{html::{Input, A}, logging::log, prelude::*, task::spawn_local};
+use tgv::{array_to_image, BitDepth, Boundary, ColorMode, DataTerm, OutputMapping, Regularization, TgvParams, TgvReport};
+use tgv_web::protocol::{pixels_to_array, Request, Response};
+use web_sys::{js_sys, HtmlInputElement};
+use image::{ImageFormat, ImageReader};
 use std::io::Cursor;
 use base64::{engine::general_purpose, Engine as _};
 // use wasm_bindgen::prelude::*;
-use ndarray::{Array2, Array3, s};
-use nshare::{self, AsNdarray3};
+use ndarray::{s, Array2, Array3, ArrayView3, Axis};
+use std::time::Duration;
 
 
-async fn convert_image_input_to_base_64(input: Option<HtmlInputElement>) -> Result<String, String> {
-    let input = input.ok_or("No input element found")?;
-    let files = input.files().ok_or("No files selected")?;
-    let file = files.get(0).ok_or("No file found")?;
+// Reads the selected file and decodes it, see `tgv::image_to_array`. None if no file
+// is selected: closing the file dialog without picking a file is not an error
+async fn read_image_file(input: Option<HtmlInputElement>) -> Result<Option<SourceImage>, AppError> {
+    let Some(file) = input.and_then(|input| input.files()).and_then(|files| files.get(0)) else {
+        return Ok(None);
+    };
 
     // Read file as ArrayBuffer
     let array_buffer_promise = file.array_buffer();
     let array_buffer = wasm_bindgen_futures::JsFuture::from(array_buffer_promise)
         .await
-        .map_err(|e| format!("Failed to read file: {:?}", e))?;
+        .map_err(|e| AppError::FileRead(format!("{:?}", e)))?;
 
     // Convert to Uint8Array and then to Vec<u8>
     let uint8_array = js_sys::Uint8Array::new(&array_buffer);
     let buffer_vec = uint8_array.to_vec();
 
-    // Decode the image
-    let img = image::load_from_memory(&buffer_vec)
-        .map_err(|e| format!("Failed to decode image: {:?}", e))?;
-    let img: RgbImage = img.into_rgb8();
-
-    // Convert original image to base64 for display
-    let mut original_buffer = Vec::new();
-    img.write_to(&mut Cursor::new(&mut original_buffer), ImageFormat::Png)
-        .map_err(|e| format!("Failed to encode original image: {:?}", e))?;
-    let original_base64 = general_purpose::STANDARD.encode(&original_buffer);
-    Ok(format!("data:image/png;base64,{}", original_base64))
+    // Detect the format from the contents, the file extension may be missing or wrong
+    let reader = || ImageReader::new(Cursor::new(&buffer_vec)).with_guessed_format()
+        .map_err(|e| AppError::FileRead(e.to_string()));
+    if reader()?.format().is_none() {
+        return Err(AppError::UnsupportedFormat(file.name()));
+    }
+    // Check the size from the header before paying for the decoding
+    let (width, height) = reader()?.into_dimensions()?;
+    if width as u64 * height as u64 > MAX_PIXELS {
+        return Err(AppError::TooLarge { width, height });
+    }
+    let img = reader()?.decode()?;
+    let (image, depth) = tgv::image_to_array(&img);
+    Ok(Some(SourceImage { file_name: file.name(), image, depth }))
 }
 
 
-async fn process_image(input: Option<HtmlInputElement>, tgv_lam: f32) -> Result<(String, String), String> {
-    let input = input.ok_or("No input element found")?;
-    let files = input.files().ok_or("No files selected")?;
-    let file = files.get(0).ok_or("No file found")?;
+// Encodes a (height, width, 3) array on the 0..255 scale as a PNG data URL for
+// display, browsers only need 8 bits
+fn png_data_url(img: &ArrayView3<f32>) -> Result<String, AppError> {
+    let mut buffer = Vec::new();
+    array_to_image(img, BitDepth::Eight).write_to(&mut Cursor::new(&mut buffer), ImageFormat::Png)
+        .map_err(|e| AppError::Encode(e.to_string()))?;
+    let base64 = general_purpose::STANDARD.encode(&buffer);
+    Ok(format!("data:image/png;base64,{}", base64))
+}
 
-    // Read file as ArrayBuffer
-    let array_buffer_promise = file.array_buffer();
-    let array_buffer = wasm_bindgen_futures::JsFuture::from(array_buffer_promise)
-        .await
-        .map_err(|e| format!("Failed to read file: {:?}", e))?;
 
-    // Convert to Uint8Array and then to Vec<u8>
-    let uint8_array = js_sys::Uint8Array::new(&array_buffer);
-    let buffer_vec = uint8_array.to_vec();
+// Shrinks a (height, width, channels) image by averaging blocks of pixels, so that
+// neither side is longer than `max_size`. Returns the image and the shrink factor
+fn downscale(img: &ArrayView3<f32>, max_size: usize) -> (Array3<f32>, usize) {
+    let (height, width, _) = img.dim();
+    let factor = height.max(width).div_ceil(max_size).max(1);
+    (shrink(img, factor), factor)
+}
 
-    // Decode the image
-    let img = image::load_from_memory(&buffer_vec)
-        .map_err(|e| format!("Failed to decode image: {:?}", e))?;
-    let img: RgbImage = img.into_rgb8();
+// Averages blocks of `factor` x `factor` pixels of a (height, width, channels) image
+fn shrink(img: &ArrayView3<f32>, factor: usize) -> Array3<f32> {
+    let (height, width, channels) = img.dim();
+    if factor == 1 {
+        return img.to_owned();
+    }
+    Array3::from_shape_fn((height.div_ceil(factor), width.div_ceil(factor), channels), |(i, j, c)| {
+        let rows = i * factor..((i + 1) * factor).min(height);
+        let cols = j * factor..((j + 1) * factor).min(width);
+        img.slice(s![rows, cols, c]).mean().unwrap()
+    })
+}
 
-    // Convert original image to base64 for display
-    let mut original_buffer = Vec::new();
-    img.write_to(&mut Cursor::new(&mut original_buffer), ImageFormat::Png)
-        .map_err(|e| format!("Failed to encode original image: {:?}", e))?;
-    let original_base64 = general_purpose::STANDARD.encode(&original_buffer);
-    let original_data_url = format!("data:image/png;base64,{}", original_base64);
+// Shrinks a missing pixel mask like `shrink`. A block is missing if any of its
+// pixels is, since its average is off then
+fn shrink_mask(mask: &Array2<bool>, factor: usize) -> Array2<bool> {
+    let mask = mask.mapv(|missing| if missing { 1f32 } else { 0. }).insert_axis(Axis(2));
+    shrink(&mask.view(), factor).remove_axis(Axis(2)).mapv(|missing| missing > 0.)
+}
 
-    // Process the image with TGV denoising
-    // let rgb_img: RgbImage = img.to_rgb8();
-    let img = img.as_ndarray3();
-    let img = img.permuted_axes([1, 2, 0]);
-    // println!("img shape is {:?}", img.shape());
-    // println!("img min is {:?}", img.into_iter().min());
-    // println!("img max is {:?}", img.into_iter().max());
-    let img: Array3<f32> = img.map(|x| *x as f32);
-    let grayscale_img: Array2<f32> = (&img.slice(s![.., .., 0]) + &img.slice(s![.., .., 1]) + &img.slice(s![.., .., 2])) / 3.0;
 
-    // println!("grayscale_img min is {:?}", (&grayscale_img).into_iter().reduce(|a, b| if a < b { a } else { b }));
-    // println!("grayscale_img max is {:?}", (&grayscale_img).into_iter().reduce(|a, b| if a > b { a } else { b }));
+// Converts the denoised array sent back by the worker into a PNG data URL
+fn encode_denoised_image(denoised_img: &Array3<f32>, mapping: OutputMapping) -> Result<String, AppError> {
+    png_data_url(&mapping.apply(&denoised_img.view()).view())
+}
 
-    let denoised_img = tgv::tgv_denoise(&grayscale_img.view(), tgv_lam, 2.0, 1.0, 0.125, 0.125, 300);
-    // WebAssembly does not allow for parallelization directly using rayon. Needs special handling
-    let denoised_img = denoised_img.map(|x| *x as u8);
-    let denoised_img = GrayImage::from_raw(img.shape()[0] as u32, img.shape()[1] as u32, denoised_img.into_iter().collect()).unwrap();
 
-    // Convert processed image to base64 for display
-    let mut processed_buffer = Vec::new();
-    denoised_img.write_to(&mut Cursor::new(&mut processed_buffer), ImageFormat::Png)
-        .map_err(|e| format!("Failed to encode processed image: {:?}", e))?;
-    let processed_base64 = general_purpose::STANDARD.encode(&processed_buffer);
-    let processed_data_url = format!("data:image/png;base64,{}", processed_base64);
+// Whether a slider moves linearly or logarithmically between `min` and `max`
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum SliderScale {
+    Linear,
+    Log,
+}
 
-    Ok((original_data_url, processed_data_url))
+impl SliderScale {
+    fn slider_position(self, value: f32) -> f32 {
+        match self {
+            SliderScale::Linear => value,
+            SliderScale::Log => value.ln(),
+        }
+    }
+
+    fn slider_value(self, position: f32) -> f32 {
+        match self {
+            SliderScale::Linear => position,
+            SliderScale::Log => position.exp(),
+        }
+    }
 }
 
+// Number of slider steps between `min` and `max` for log scale sliders
+const LOG_SLIDER_STEPS: f32 = 200.0;
 
+// Slider and number input bound to the same parameter. The signal always holds the
+// parameter value itself; the slider works in log space when `scale` is `Log`.
 #[component]
-fn SyncedControl(tgv_lam: ReadSignal<f32>, tgv_lam_setter: WriteSignal<f32>) -> impl IntoView {
-    // Reactive state for our parameter (range 0.0–1.0)
+fn SyncedControl(
+    label: &'static str,
+    value: ReadSignal<f32>,
+    value_setter: WriteSignal<f32>,
+    min: f32,
+    max: f32,
+    // Step of the linear slider and of the number input
+    step: f32,
+    #[prop(default = SliderScale::Linear)] scale: SliderScale,
+) -> impl IntoView {
+    let slider_step = match scale {
+        SliderScale::Linear => step,
+        SliderScale::Log => (max.ln() - min.ln()) / LOG_SLIDER_STEPS,
+    };
 
     view! {
       <div style="display: flex; align-items: center; gap: 8px;">
-        // Slider: -1e-12 to 1e3 but in log scale
+        <label style="width: 5em;">{label}</label>
         <input
           type="range"
-          min="-12"
-          max="3"
-          step="0.1"
+          min=scale.slider_position(min).to_string()
+          max=scale.slider_position(max).to_string()
+          step=slider_step.to_string()
           // Bind slider thumb to param
-          prop:value=move || tgv_lam.get().to_string()  // Let the slider cursor go from min to max
+          prop:value=move || scale.slider_position(value.get()).to_string()
           // On input, parse and update `param`
           on:input=move |ev| {
             let v = event_target_value(&ev)
                       .parse::<f32>()
-                    //   .map(|x| x.exp())
-                      .unwrap_or(tgv_lam.get());
-                    // tgv_lam.set(v);
-            tgv_lam_setter.set(v);
+                      .map(|x| scale.slider_value(x))
+                      .unwrap_or(value.get());
+            value_setter.set(v);
           }
         />
 
-        // Number input: shows same param but in linear scale
+        // Number input: shows the same param in linear scale
         <input
           type="number"
-          step="1e-3"
-          min="1e-12"
-          max="1e3"
-          prop:value=move || tgv_lam.get().exp().to_string()  // Show hte displayed value in log scale
-        //   On input, parse and update `param`
+          step=step.to_string()
+          min=min.to_string()
+          max=max.to_string()
+          prop:value=move || value.get().to_string()
           on:input=move |ev| {
             let v = event_target_value(&ev)
                       .parse::<f32>()
-                      .map(|x| x.ln())
-                      .unwrap_or(tgv_lam.get());  // Int inputted, store it as log scale
-            // param_set.set(v);
-            tgv_lam_setter.set(v);
+                      .unwrap_or(value.get());
+            value_setter.set(v);
           }
-          style="width: 4em;"
+          style="width: 5em;"
         />
       </div>
     }
 }
 
 
+// Drop-down over a fixed list of options, e.g. `ColorMode::ALL`
+#[component]
+fn EnumSelect<T>(
+    label: &'static str,
+    options: &'static [T],
+    value: ReadSignal<T>,
+    value_setter: WriteSignal<T>,
+    option_label: fn(&T) -> &'static str,
+) -> impl IntoView
+where
+    T: Copy + PartialEq + Send + Sync + 'static,
+{
+    view! {
+      <div style="display: flex; align-items: center; gap: 8px;">
+        <label style="width: 5em;">{label}</label>
+        <select
+          on:change=move |ev| {
+            // Options are identified by their index in `options`
+            let v = event_target_value(&ev)
+                      .parse::<usize>()
+                      .ok()
+                      .and_then(|i| options.get(i).copied())
+                      .unwrap_or(value.get());
+            value_setter.set(v);
+          }
+        >
+          {options.iter().enumerate().map(|(i, option)| view! {
+            <option value=i.to_string() selected=move || value.get() == *option>{option_label(option)}</option>
+          }).collect_view()}
+        </select>
+      </div>
+    }
+}
+
+
+// The uploaded image, decoded once so that every run only pays for the solver
+struct SourceImage {
+    file_name: String,
+    // (height, width, 3) RGB values on the 0..255 scale
+    image: Array3<f32>,
+    // Bit depth the file was stored at
+    depth: BitDepth,
+}
+
+// Final result of a job, kept at full precision for exporting
+struct DenoisedImage {
+    image: Array3<f32>,
+    source: JobSource,
+}
+
+// Where the image of a job came from and how it was denoised
+#[derive(Clone)]
+struct JobSource {
+    file_name: String,
+    depth: BitDepth,
+    params: TgvParams,
+    // How much the image was shrunk before denoising, 1 for full resolution
+    downscale: usize,
+}
+
+// Contrast stretch of the displayed result, if enabled
+const DISPLAY_STRETCH: OutputMapping = OutputMapping::Percentile { low: 0.5, high: 99.5 };
+
+// Maps the largest differences between original and result to white
+const DIFFERENCE_STRETCH: OutputMapping = OutputMapping::Percentile { low: 0.0, high: 99.5 };
+
+// Iterations between two previews of the intermediate result
+const PREVIEW_INTERVAL: usize = 20;
+
+// Automatic runs wait this long after the last parameter change
+const AUTO_UPDATE_DELAY: Duration = Duration::from_millis(300);
+
+// Automatic runs denoise a copy of the image shrunk to at most this many pixels per
+// side, so they finish while the user is still tuning. The result only approximates
+// the full resolution one, since the averaging also reduces the noise
+const AUTO_UPDATE_SIZE: usize = 512;
+
+// Size of the residual plot, in SVG user units
+const PLOT_WIDTH: f32 = 400.0;
+const PLOT_HEIGHT: f32 = 160.0;
+
+// Residual histories of the last run on a log scale, a primal and a dual line per
+// solver run. Shows nothing before the first run.
+#[component]
+fn ResidualPlot(reports: ReadSignal<Vec<TgvReport>>) -> impl IntoView {
+    let plot = move || {
+        let reports = reports.get();
+        let residuals = || reports.iter().flat_map(|report| &report.history);
+        let log_values = || residuals()
+            .flat_map(|r| [r.primal, r.dual])
+            .filter(|value| *value > 0.)
+            .map(f32::log10);
+        // Whole decades, so the axis labels are round numbers
+        let low = log_values().fold(f32::INFINITY, f32::min).floor();
+        let high = log_values().fold(f32::NEG_INFINITY, f32::max).ceil().max(low + 1.);
+        if !low.is_finite() {
+            return None;
+        }
+        let max_iteration = residuals().map(|r| r.iteration).max().unwrap_or(1) as f32;
+
+        let polyline = |values: Vec<(usize, f32)>| values.iter()
+            .map(|&(iteration, value)| {
+                let x = PLOT_WIDTH * iteration as f32 / max_iteration;
+                let y = PLOT_HEIGHT * (high - value.log10().max(low)) / (high - low);
+                format!("{:.1},{:.1}", x, y)
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        let lines = reports.iter().map(|report| {
+            let primal = polyline(report.history.iter().map(|r| (r.iteration, r.primal)).collect());
+            let dual = polyline(report.history.iter().map(|r| (r.iteration, r.dual)).collect());
+            view! {
+                <polyline points=primal fill="none" stroke="steelblue" />
+                <polyline points=dual fill="none" stroke="darkorange" />
+            }
+        }).collect_view();
+
+        let iterations = reports.iter().map(|report| report.iterations).max().unwrap_or(0);
+        let converged = reports.iter().all(|report| report.converged);
+        let summary = format!("{} iterations{}", iterations, if converged { ", converged" } else { "" });
+
+        Some(view! {
+            <div class="residual-plot">
+                <h2>"Residuals"</h2>
+                <svg
+                    width=PLOT_WIDTH + 60.
+                    height=PLOT_HEIGHT + 30.
+                    viewBox=format!("-50 -10 {} {}", PLOT_WIDTH + 60., PLOT_HEIGHT + 30.)
+                >
+                    <rect width=PLOT_WIDTH height=PLOT_HEIGHT fill="none" stroke="lightgray" />
+                    <text x="-5" y="5" text-anchor="end" font-size="10">{format!("1e{}", high)}</text>
+                    <text x="-5" y=PLOT_HEIGHT text-anchor="end" font-size="10">{format!("1e{}", low)}</text>
+                    <text x=PLOT_WIDTH y=PLOT_HEIGHT + 15. text-anchor="end" font-size="10">{max_iteration}</text>
+                    {lines}
+                </svg>
+                <div>
+                    <span style="color: steelblue;">"primal"</span>" / "
+                    <span style="color: darkorange;">"dual"</span>", "{summary}
+                </div>
+            </div>
+        })
+    };
+
+    view! { {plot} }
+}
+
+
 #[component]
 fn App() -> impl IntoView {
     let file_input: NodeRef<Input> = NodeRef::new();
     let (original_img_src, set_original_img_src) = signal(String::new());
     let (processed_img_src, set_processed_img_src) = signal(String::new());
     let (is_processing, set_is_processing) = signal(false);
-    let (error_message, set_error_message) = signal(String::new());
-    let (tgv_lam, set_tgv_lam) = signal(0.5 as f32);
-
-
-    // Use spawn_local directly in the click handler instead of Action
-    let on_process = move |_| {
-        let file_input_clone = file_input.get();
+    let (progress, set_progress) = signal(0.0f32);
+    let (error_message, set_error_message) = signal(None::<AppError>);
+    let defaults = TgvParams { lambda: 0.5_f32.exp(), ..TgvParams::default() };
+    let (tgv_lam, set_tgv_lam) = signal(defaults.lambda);
+    let (alpha0, set_alpha0) = signal(defaults.alpha0);
+    let (alpha1, set_alpha1) = signal(defaults.alpha1);
+    let (tau, set_tau) = signal(defaults.tau);
+    let (sigma, set_sigma) = signal(defaults.sigma);
+    // Kept as f32 so it can share `SyncedControl` with the other parameters
+    let (n_iter, set_n_iter) = signal(defaults.n_iter as f32);
+    let (boundary, set_boundary) = signal(defaults.boundary);
+    let (regularization, set_regularization) = signal(defaults.regularization);
+    let (huber_epsilon, set_huber_epsilon) = signal(defaults.huber_epsilon);
+    let (data_term, set_data_term) = signal(defaults.data_term);
+    // Photon counts per sample value of the image, for the KL data term
+    let (gain, set_gain) = signal(defaults.count_scale);
+    let (color_mode, set_color_mode) = signal(ColorMode::Vectorial);
+    let (early_stop, set_early_stop) = signal(false);
+    let (tolerance, set_tolerance) = signal(1e-2f32);
+    let (reports, set_reports) = signal(Vec::<TgvReport>::new());
+    // Iterations behind the displayed result while it is still a preview
+    let (preview_iteration, set_preview_iteration) = signal(None::<usize>);
+    // Source of the job behind the displayed result, which the controls may no
+    // longer match
+    let (result_source, set_result_source) = signal(None::<JobSource>);
+    // Shrink factor of the image behind the displayed result
+    let result_downscale = move || result_source.with(|source| source.as_ref().map_or(1, |source| source.downscale));
+    let result_params = move || result_source.with(|source| source.as_ref().map(|source| source.params));
+    let (auto_update, set_auto_update) = signal(false);
+    let (blur_kind, set_blur_kind) = signal(BlurKind::None);
+    let (blur_sigma, set_blur_sigma) = signal(1.0f32);
+    let (motion_length, set_motion_length) = signal(5.0f32);
+    let (motion_angle, set_motion_angle) = signal(0.0f32);
+    let psf_input: NodeRef<Input> = NodeRef::new();
+    // Uploaded point spread function as a (height, width, 1) gray image
+    let (psf, set_psf) = signal_local(None::<Array3<f32>>);
+    let (inpaint, set_inpaint) = signal(false);
+    // Pixels to fill in when inpainting, None if none are marked
+    let (mask, set_mask) = signal(None::<Array2<bool>>);
+
+    let params = move || TgvParams {
+        lambda: tgv_lam.get(),
+        alpha0: alpha0.get(),
+        alpha1: alpha1.get(),
+        tau: tau.get(),
+        sigma: sigma.get(),
+        n_iter: n_iter.get().round() as usize,
+        boundary: boundary.get(),
+        regularization: regularization.get(),
+        huber_epsilon: huber_epsilon.get(),
+        data_term: data_term.get(),
+        count_scale: gain.get(),
+        tolerance: early_stop.get().then(|| tolerance.get()),
+        ..defaults
+    };
+    let blur = move || Blur {
+        kind: blur_kind.get(),
+        sigma: blur_sigma.get(),
+        length: motion_length.get(),
+        angle: motion_angle.get(),
+    };
+    // Deblurring needs smaller steps, see `TgvParams::validate_for_deblurring`
+    let params_error = move || {
+        let kind = blur_kind.get();
+        let valid = if kind == BlurKind::None { params().validate() } else { params().validate_for_deblurring() }
+            .and_then(|()| color_mode.get().validate(&params()));
+        match valid {
+            Err(e) => Some(e.to_string()),
+            Ok(()) if kind == BlurKind::Psf && psf.with(Option::is_none) => Some("Upload a point spread function to deblur".to_string()),
+            Ok(()) if inpaint.get() && mask.with(Option::is_none) => Some("Paint or load a mask of the pixels to fill in".to_string()),
+            Ok(()) => None,
+        }
+    };
 
-        // Clone the setters to avoid capturing references
-        let set_original_img_src = set_original_img_src.clone();
-        let set_processed_img_src = set_processed_img_src.clone();
-        let set_is_processing = set_is_processing.clone();
-        let set_error_message = set_error_message.clone();
-        // let tgv_lam = 
+    // Id of the latest job sent to the worker, so stale responses can be ignored
+    let current_job = StoredValue::new(0u32);
+    // Source of the latest job, for naming and encoding its download
+    let current_job_source = StoredValue::new(None::<JobSource>);
+    let denoised = StoredValue::new_local(None::<DenoisedImage>);
+    let (source_image, set_source_image) = signal_local(None::<SourceImage>);
+    let (export_format, set_export_format) = signal(ExportFormat::Png);
+    let download_link: NodeRef<A> = NodeRef::new();
+    // Only changes how the result is shown, downloads keep the actual values
+    let (stretch_display, set_stretch_display) = signal(false);
+    let display_mapping = move || if stretch_display.get() { DISPLAY_STRETCH } else { OutputMapping::Clamp };
+    let (compare_mode, set_compare_mode) = signal(CompareMode::Split);
+    let (difference_img_src, set_difference_img_src) = signal(String::new());
+
+    // The solver runs in a Web Worker so the page stays responsive while denoising
+    let on_response = move |response: Response, pixels: Option<Vec<f32>>| {
+        match response {
+            Response::Progress { job, done, total } if job == current_job.get_value() => {
+                set_progress.set(done as f32 / total as f32);
+            },
+            Response::Preview { job, done, height, width, channels } if job == current_job.get_value() => {
+                let preview = pixels_to_array(height, width, channels, pixels)
+                    .map_err(AppError::Worker)
+                    .and_then(|img| encode_denoised_image(&img, display_mapping()));
+                match preview {
+                    Ok(preview) => {
+                        set_processed_img_src.set(preview);
+                        set_preview_iteration.set(Some(done));
+                        set_result_source.set(current_job_source.get_value());
+                    },
+                    Err(err) => set_error_message.set(Some(err)),
+                }
+            },
+            Response::Done { job, height, width, channels, reports } if job == current_job.get_value() => {
+                let processed = pixels_to_array(height, width, channels, pixels)
+                    .map_err(AppError::Worker)
+                    .and_then(|img| encode_denoised_image(&img, display_mapping()).map(|src| (img, src)));
+                match processed {
+                    Ok((image, src)) => {
+                        set_processed_img_src.set(src);
+                        set_preview_iteration.set(None);
+                        set_reports.set(reports);
+                        let source = current_job_source.get_value();
+                        set_result_source.set(source.clone());
+                        denoised.set_value(source.map(|source| DenoisedImage { image, source }));
+                    },
+                    Err(err) => set_error_message.set(Some(err)),
+                }
+                set_is_processing.set(false);
+            },
+            Response::Failed { job, message } if job == current_job.get_value() => {
+                set_error_message.set(Some(AppError::Solver(message)));
+                set_is_processing.set(false);
+            },
+            _ => {}
+        }
+    };
+    let spawn_denoiser = move || match Denoiser::spawn(on_response) {
+        Ok(denoiser) => Some(denoiser),
+        Err(err) => {
+            log!("{}", err);
+            set_error_message.set(Some(err));
+            None
+        }
+    };
+    let denoiser = StoredValue::new_local(spawn_denoiser());
+
+    // The worker cannot be interrupted, so replace it with a fresh one. Bumping the
+    // job id makes sure nothing from the cancelled job is shown
+    let cancel_job = move || {
+        current_job.update_value(|job| *job += 1);
+        denoiser.set_value(spawn_denoiser());
+        set_is_processing.set(false);
+        set_progress.set(0.0);
+    };
+    let on_cancel = move |_| cancel_job();
+
+    // Drops the result of the previous image, and the job still working on it
+    let clear_result = move || {
+        if is_processing.get_untracked() {
+            cancel_job();
+        }
+        denoised.set_value(None);
+        current_job_source.set_value(None);
+        set_processed_img_src.set(String::new());
+        set_difference_img_src.set(String::new());
+        set_preview_iteration.set(None);
+        set_result_source.set(None);
+        set_reports.set(Vec::new());
+    };
 
-        set_is_processing.set(true);
-        set_error_message.set(String::new());
+    // Redraw the final result when the display mapping changes. Previews pick it up
+    // with the next update
+    Effect::new(move |_| {
+        let mapping = display_mapping();
+        if preview_iteration.get_untracked().is_some() {
+            return;
+        }
+        let redrawn = denoised.with_value(|denoised| {
+            denoised.as_ref().map(|denoised| encode_denoised_image(&denoised.image, mapping))
+        });
+        match redrawn {
+            Some(Ok(src)) => set_processed_img_src.set(src),
+            Some(Err(err)) => set_error_message.set(Some(err)),
+            None => {}
+        }
+    });
+
+    // Only computed while it is shown, and only for final full resolution results,
+    // the only ones kept at full precision
+    Effect::new(move |_| {
+        let _ = processed_img_src.get();
+        if compare_mode.get() != CompareMode::Difference || preview_iteration.get().is_some() || result_downscale() != 1 {
+            set_difference_img_src.set(String::new());
+            return;
+        }
+        let diff = source_image.with(|source| denoised.with_value(|denoised| match (source, denoised) {
+            (Some(source), Some(denoised)) if source.image.dim() == denoised.image.dim() => {
+                let diff = difference(&source.image.view(), &denoised.image.view());
+                Some(png_data_url(&DIFFERENCE_STRETCH.apply(&diff.view()).view()))
+            },
+            _ => None,
+        }));
+        match diff {
+            Some(Ok(src)) => set_difference_img_src.set(src),
+            Some(Err(err)) => set_error_message.set(Some(err)),
+            None => set_difference_img_src.set(String::new()),
+        }
+    });
+
+    let on_download = move |_| {
+        let format = export_format.get();
+        let saved = denoised.with_value(|denoised| {
+            let denoised = denoised.as_ref().ok_or(AppError::Encode("nothing to download yet".to_string()))?;
+            let anchor = download_link.get().ok_or(AppError::Encode("download link not found".to_string()))?;
+            let source = &denoised.source;
+            let bytes = format.encode(&denoised.image, source.depth)?;
+            download(&anchor, &bytes, format, &format.file_name(&source.file_name, &source.params))
+        });
+        if let Err(err) = saved {
+            set_error_message.set(Some(err));
+        }
+    };
 
+    // Sends the uploaded image to the worker, shrunk to at most `max_size` pixels per
+    // side if given. A job still running is cancelled first
+    let start_job = move |max_size: Option<usize>| {
+        let params = params();
+        if let Some(err) = params_error() {
+            set_error_message.set(Some(AppError::Solver(err)));
+            return;
+        }
+        let color_mode = color_mode.get();
+        let blur = blur();
+        let inpaint = inpaint.get_untracked();
+        if is_processing.get_untracked() {
+            cancel_job();
+        }
+
+        let sent = source_image.with_untracked(|source| {
+            let source = source.as_ref().ok_or(AppError::NoFile)?;
+            let (image, downscale) = match max_size {
+                Some(max_size) => downscale(&source.image.view(), max_size),
+                None => (source.image.clone(), 1),
+            };
+            // The pixels are on the 0..255 scale, and a shrunk pixel averages the
+            // counts of downscale^2 pixels
+            let params = TgvParams {
+                count_scale: params.count_scale * source.depth.counts_per_unit() * (downscale * downscale) as f32,
+                ..params
+            };
+            let kernel = psf.with_untracked(|psf| blur.kernel(psf.as_ref(), downscale))?;
+            let missing = if inpaint { mask.with_untracked(|mask| mask.as_ref().map(|mask| shrink_mask(mask, downscale))) } else { None };
+            let job = current_job.get_value() + 1;
+            current_job.set_value(job);
+            current_job_source.set_value(Some(JobSource { file_name: source.file_name.clone(), depth: source.depth, params, downscale }));
+            let (height, width, channels) = image.dim();
+            let request = Request::Denoise {
+                job,
+                height,
+                width,
+                channels,
+                params,
+                color_mode,
+                kernel,
+                inpaint: missing.is_some(),
+                preview_interval: Some(PREVIEW_INTERVAL),
+            };
+            // The mask of an inpainting job follows the image, see `Request::Denoise`
+            let mask = missing.iter().flatten().map(|&missing| if missing { 1. } else { 0. });
+            let pixels: Vec<f32> = image.iter().copied().chain(mask).collect();
+            denoiser.with_value(|denoiser| match denoiser {
+                Some(denoiser) => denoiser.post(&request, Some(&pixels)),
+                None => Err(AppError::Worker("not running".to_string())),
+            })
+        });
+        match sent {
+            Ok(()) => {
+                set_is_processing.set(true);
+                set_progress.set(0.0);
+                set_error_message.set(None);
+            },
+            Err(err) => set_error_message.set(Some(err)),
+        }
+    };
+    let on_process = move |_| start_job(None);
+
+    // In auto update mode, re-run on a shrunk image once the parameters have not
+    // changed for a moment. Process still denoises the full resolution image
+    let pending_update = StoredValue::new(None::<TimeoutHandle>);
+    Effect::new(move |_| {
+        let _ = (params(), color_mode.get(), blur(), psf.with(Option::is_some), inpaint.get());
+        mask.track();
+        let has_image = source_image.with(Option::is_some);
+        if let Some(handle) = pending_update.get_value() {
+            handle.clear();
+        }
+        pending_update.set_value(None);
+        if !auto_update.get() || !has_image || params_error().is_some() {
+            return;
+        }
+        match set_timeout_with_handle(move || start_job(Some(AUTO_UPDATE_SIZE)), AUTO_UPDATE_DELAY) {
+            Ok(handle) => pending_update.set_value(Some(handle)),
+            Err(e) => log!("Failed to schedule update: {:?}", e),
+        }
+    });
+
+    // Decode the new file right away and keep it for all later runs
+    let update_image = move |_| {
         spawn_local(async move {
-            match process_image(file_input_clone, tgv_lam.get().exp()).await {
-                Ok((original, processed)) => {
-                    set_original_img_src.set(original);
-                    set_processed_img_src.set(processed);
-                    set_is_processing.set(false);
+            let input_element = file_input.get();
+            let loaded = read_image_file(input_element).await
+                .and_then(|source| source.map(|source| Ok((png_data_url(&source.image.view())?, source))).transpose());
+            clear_result();
+            match loaded {
+                Ok(Some((src, source))) => {
+                    set_original_img_src.set(src);
+                    set_source_image.set(Some(source));
+                    set_error_message.set(None);
+                },
+                failed => {
+                    set_original_img_src.set(String::new());
+                    set_source_image.set(None);
+                    if let Err(err) = failed {
+                        set_error_message.set(Some(err));
+                    }
                 },
-                Err(err) => {
-                    set_error_message.set(err);
-                    set_is_processing.set(false);
-                }
             }
-        });
+        })
     };
 
-    let update_image = move |_| {
+    let update_psf = move |_| {
         spawn_local(async move {
-                let input_element = file_input.get();
-                set_original_img_src.set(convert_image_input_to_base_64(input_element).await.unwrap_or_default());
+            match read_image_file(psf_input.get()).await {
+                Ok(Some(source)) => {
+                    set_psf.set(Some(source.image.mean_axis(Axis(2)).unwrap().insert_axis(Axis(2))));
+                    set_error_message.set(None);
+                },
+                failed => {
+                    set_psf.set(None);
+                    if let Err(err) = failed {
+                        set_error_message.set(Some(err));
+                    }
+                },
             }
-        )
+        })
     };
 
     view! {
@@ -204,33 +695,155 @@ fn App() -> impl IntoView {
                 />
                 <button
                     on:click=on_process
-                    disabled=is_processing
+                    // Auto update jobs may be replaced by a full resolution one at any time
+                    disabled=move || (is_processing.get() && !auto_update.get()) || params_error().is_some() || source_image.with(Option::is_none)
                 >
                 // "Process Image"
-                    {move || if is_processing.get() { "Processing..." } else { "Process Image" }}
+                    {move || if is_processing.get() {
+                        format!("Processing... {:.0}%", 100.0 * progress.get())
+                    } else {
+                        "Process Image".to_string()
+                    }}
                 </button>
+                <Show when=move || is_processing.get()>
+                    <progress max="1" value=move || progress.get().to_string()></progress>
+                    <button on:click=on_cancel>"Cancel"</button>
+                </Show>
+            </div>
+            <div class="parameter-panel">
+                <SyncedControl label="lambda" value=tgv_lam value_setter=set_tgv_lam min=1e-3 max=1e3 step=1e-3 scale=SliderScale::Log />
+                // Only TGV has a second order term
+                <Show when=move || regularization.get() == Regularization::Tgv>
+                    <SyncedControl label="alpha0" value=alpha0 value_setter=set_alpha0 min=1e-2 max=1e2 step=1e-2 scale=SliderScale::Log />
+                </Show>
+                <SyncedControl label="alpha1" value=alpha1 value_setter=set_alpha1 min=1e-2 max=1e2 step=1e-2 scale=SliderScale::Log />
+                <SyncedControl label="tau" value=tau value_setter=set_tau min=1e-3 max=1.0 step=1e-3 scale=SliderScale::Log />
+                <SyncedControl label="sigma" value=sigma value_setter=set_sigma min=1e-3 max=1.0 step=1e-3 scale=SliderScale::Log />
+                <SyncedControl label="iterations" value=n_iter value_setter=set_n_iter min=1.0 max=2000.0 step=1.0 />
+                <EnumSelect label="color mode" options=&ColorMode::ALL value=color_mode value_setter=set_color_mode option_label=ColorMode::label />
+                <EnumSelect label="regularizer" options=&Regularization::ALL value=regularization value_setter=set_regularization option_label=Regularization::label />
+                <Show when=move || regularization.get() == Regularization::HuberTv>
+                    <SyncedControl label="epsilon" value=huber_epsilon value_setter=set_huber_epsilon min=1e-2 max=1e2 step=1e-2 scale=SliderScale::Log />
+                </Show>
+                <EnumSelect label="data term" options=&DataTerm::ALL value=data_term value_setter=set_data_term option_label=DataTerm::label />
+                <Show when=move || data_term.get() == DataTerm::Kl>
+                    <SyncedControl label="gain" value=gain value_setter=set_gain min=1e-3 max=1e3 step=1e-3 scale=SliderScale::Log />
+                </Show>
+                <EnumSelect label="blur" options=&BlurKind::ALL value=blur_kind value_setter=set_blur_kind option_label=BlurKind::label />
+                <Show when=move || blur_kind.get() == BlurKind::Gaussian>
+                    <SyncedControl label="blur sigma" value=blur_sigma value_setter=set_blur_sigma min=0.1 max=20.0 step=0.1 scale=SliderScale::Log />
+                </Show>
+                <Show when=move || blur_kind.get() == BlurKind::Motion>
+                    <SyncedControl label="length" value=motion_length value_setter=set_motion_length min=1.0 max=100.0 step=0.5 scale=SliderScale::Log />
+                    <SyncedControl label="angle" value=motion_angle value_setter=set_motion_angle min=0.0 max=180.0 step=1.0 />
+                </Show>
+                // Kept in the page while hidden, so the uploaded PSF stays selected
+                <div style=move || format!("display: {}; align-items: center; gap: 8px;", if blur_kind.get() == BlurKind::Psf { "flex" } else { "none" })>
+                    <label style="width: 5em;">"PSF"</label>
+                    <input type="file" accept="image/*" node_ref=psf_input on:change=update_psf />
+                </div>
+                <EnumSelect label="boundary" options=&Boundary::ALL value=boundary value_setter=set_boundary option_label=Boundary::label />
+                <div style="display: flex; align-items: center; gap: 8px;">
+                    <label style="width: 5em;">"stop early"</label>
+                    <input
+                        type="checkbox"
+                        prop:checked=early_stop
+                        on:change=move |ev| set_early_stop.set(event_target_checked(&ev))
+                    />
+                </div>
+                <Show when=move || early_stop.get()>
+                    <SyncedControl label="tolerance" value=tolerance value_setter=set_tolerance min=1e-4 max=10.0 step=1e-4 scale=SliderScale::Log />
+                </Show>
+                <div style="display: flex; align-items: center; gap: 8px;">
+                    <label style="width: 5em;">"inpaint"</label>
+                    <input
+                        type="checkbox"
+                        prop:checked=inpaint
+                        on:change=move |ev| set_inpaint.set(event_target_checked(&ev))
+                    />
+                </div>
+                <div style="display: flex; align-items: center; gap: 8px;">
+                    <label style="width: 5em;">"auto update"</label>
+                    <input
+                        type="checkbox"
+                        prop:checked=auto_update
+                        on:change=move |ev| set_auto_update.set(event_target_checked(&ev))
+                    />
+                </div>
+                {move || params_error().map(|err| view! {
+                    <div class="parameter-warning" style="color: darkorange;">{err}</div>
+                })}
             </div>
-            <SyncedControl tgv_lam=tgv_lam tgv_lam_setter=set_tgv_lam />
 
-            // {move || error_message().as_str().is_empty().then(|| view! {
-            //     <div class="error-message">{error_message}</div>
-            // })}
+            // Kept in the page while hidden, so the painted mask survives turning
+            // inpainting off and on
+            <div
+                class="mask-section"
+                style:display=move || if inpaint.get() && !original_img_src.get().is_empty() { "block" } else { "none" }
+            >
+                <h2>"Pixels to fill in"</h2>
+                <MaskPainter
+                    image=original_img_src.into()
+                    size=Signal::derive(move || source_image.with(|source| source.as_ref().map(|source| {
+                        let (height, width, _) = source.image.dim();
+                        (height, width)
+                    })))
+                    set_mask=set_mask
+                    set_error=set_error_message
+                />
+            </div>
+
+            {move || error_message.get().map(|err| view! {
+                <div
+                    class="error-banner"
+                    role="alert"
+                    style="display: flex; align-items: center; justify-content: space-between; gap: 8px; padding: 8px 12px; margin: 8px 0; color: darkred; background: mistyrose; border: 1px solid darkred; border-radius: 4px;"
+                >
+                    <span>{err.to_string()}</span>
+                    <button aria-label="Dismiss" on:click=move |_| set_error_message.set(None)>"×"</button>
+                </div>
+            })}
 
             <div class="image-container">
                 <Show when=move || !original_img_src.get().is_empty()>
-                    <div class="image-box">
-                        <h2>"Original Image"</h2>
-                        <img src=original_img_src alt="Original Image" />
-                    </div>
+                    <CompareViewer
+                        original=original_img_src.into()
+                        processed=processed_img_src.into()
+                        difference=difference_img_src.into()
+                        width=Signal::derive(move || source_image.with(|source| source.as_ref().map(|source| source.image.dim().1)))
+                        mode=compare_mode
+                        set_mode=set_compare_mode
+                    />
                 </Show>
 
                 <Show when=move || !processed_img_src.get().is_empty()>
                     <div class="image-box">
-                        <h2>"Denoised Image, " {move || format!("lambda = {:.3}", tgv_lam.get().exp())}</h2>
-                        <img src=processed_img_src alt="Denoised Image" />
+                        <h2>
+                            "Denoised Image, " {move || result_params().map(|params| format!("{}, lambda = {:.3}", params.regularization.label(), params.lambda))}
+                            {move || preview_iteration.get().map(|done| format!(" (preview after {} iterations)", done))}
+                            {move || (result_downscale() > 1).then(|| format!(" (at 1/{} resolution)", result_downscale()))}
+                        </h2>
+                        <label>
+                            <input
+                                type="checkbox"
+                                prop:checked=stretch_display
+                                on:change=move |ev| set_stretch_display.set(event_target_checked(&ev))
+                            />
+                            "Stretch contrast (display only)"
+                        </label>
+                        // Only the final full resolution result can be saved, not a preview
+                        <Show when=move || preview_iteration.get().is_none() && !is_processing.get() && result_downscale() == 1>
+                            <div class="download" style="display: flex; align-items: center; gap: 8px;">
+                                <EnumSelect label="format" options=&ExportFormat::ALL value=export_format value_setter=set_export_format option_label=ExportFormat::label />
+                                <button on:click=on_download>"Download"</button>
+                            </div>
+                        </Show>
+                        <a node_ref=download_link style="display: none;"></a>
                     </div>
                 </Show>
             </div>
+
+            <ResidualPlot reports=reports />
         </div>
     }
 }
@@ -0,0 +1,177 @@
+// Editor for the mask of pixels to fill in when inpainting. The mask is painted with
+// the pointer onto a canvas over the original image, or loaded from an image file
+// whose white pixels are missing. The canvas holds the mask: a pixel is missing
+// where it is painted.
+
+use leptos::{ev, html::{Canvas, Input}, prelude::*, task::spawn_local};
+use ndarray::{Array2, Axis};
+use web_sys::{
+    wasm_bindgen::{Clamped, JsCast},
+    CanvasRenderingContext2d, Element, HtmlCanvasElement, ImageData,
+};
+
+use crate::{error::AppError, read_image_file};
+
+// Color of the painted pixels. The canvas is drawn half transparent
+const MASK_COLOR: &str = "red";
+
+fn context(canvas: &NodeRef<Canvas>) -> Option<CanvasRenderingContext2d> {
+    canvas.get_untracked()?.get_context("2d").ok()??.dyn_into().ok()
+}
+
+// Pointer position in canvas pixels, which are image pixels
+fn canvas_position(ev: &ev::PointerEvent) -> Option<(f64, f64)> {
+    let canvas = ev.current_target()?.dyn_into::<HtmlCanvasElement>().ok()?;
+    let rect = canvas.get_bounding_client_rect();
+    let x = (ev.client_x() as f64 - rect.left()) * canvas.width() as f64 / rect.width();
+    let y = (ev.client_y() as f64 - rect.top()) * canvas.height() as f64 / rect.height();
+    Some((x, y))
+}
+
+// The missing pixels, or None if nothing is painted
+fn read_mask(context: &CanvasRenderingContext2d, (height, width): (usize, usize)) -> Option<Array2<bool>> {
+    let data = context.get_image_data(0., 0., width as f64, height as f64).ok()?.data();
+    let mask = Array2::from_shape_vec((height, width), data.chunks_exact(4).map(|rgba| rgba[3] > 0).collect()).ok()?;
+    mask.iter().any(|missing| *missing).then_some(mask)
+}
+
+// `image` is the original as a data URL and `size` its (height, width). The mask
+// is cleared whenever the image changes
+#[component]
+pub fn MaskPainter(
+    image: Signal<String>,
+    size: Signal<Option<(usize, usize)>>,
+    set_mask: WriteSignal<Option<Array2<bool>>>,
+    set_error: WriteSignal<Option<AppError>>,
+) -> impl IntoView {
+    let canvas: NodeRef<Canvas> = NodeRef::new();
+    let mask_input: NodeRef<Input> = NodeRef::new();
+    let (brush_size, set_brush_size) = signal(8.0f32);
+    let (erase, set_erase) = signal(false);
+    // Last painted position while the pointer is down
+    let last = StoredValue::new(None::<(f64, f64)>);
+
+    let clear = move || {
+        if let (Some(context), Some((height, width))) = (context(&canvas), size.get_untracked()) {
+            context.clear_rect(0., 0., width as f64, height as f64);
+        }
+        set_mask.set(None);
+    };
+    Effect::new(move |_| {
+        let _ = image.get();
+        clear();
+    });
+    let publish = move || {
+        let mask = context(&canvas).zip(size.get_untracked()).and_then(|(context, size)| read_mask(&context, size));
+        set_mask.set(mask);
+    };
+
+    // Paints a stroke from the last position to (x, y), or erases it
+    let paint = move |(x, y): (f64, f64)| {
+        let Some(context) = context(&canvas) else { return };
+        let radius = brush_size.get_untracked() as f64 / 2.;
+        let (x0, y0) = last.get_value().unwrap_or((x, y));
+        context.set_global_composite_operation(if erase.get_untracked() { "destination-out" } else { "source-over" }).ok();
+        context.set_fill_style_str(MASK_COLOR);
+        context.set_stroke_style_str(MASK_COLOR);
+        context.set_line_width(2. * radius);
+        context.set_line_cap("round");
+        context.begin_path();
+        context.move_to(x0, y0);
+        context.line_to(x, y);
+        context.stroke();
+        // A click without moving draws a dot
+        context.begin_path();
+        context.arc(x, y, radius, 0., std::f64::consts::TAU).ok();
+        context.fill();
+        last.set_value(Some((x, y)));
+    };
+    let on_pointer_down = move |ev: ev::PointerEvent| {
+        let Some(position) = canvas_position(&ev) else { return };
+        if let Some(element) = ev.current_target().and_then(|target| target.dyn_into::<Element>().ok()) {
+            let _ = element.set_pointer_capture(ev.pointer_id());
+        }
+        last.set_value(None);
+        paint(position);
+    };
+    let on_pointer_move = move |ev: ev::PointerEvent| {
+        if last.get_value().is_some() && let Some(position) = canvas_position(&ev) {
+            paint(position);
+        }
+    };
+    // The mask is only read back once a stroke is done
+    let on_pointer_up = move |_: ev::PointerEvent| {
+        if last.get_value().is_some() {
+            last.set_value(None);
+            publish();
+        }
+    };
+
+    // Replaces the painted mask by the white pixels of an uploaded image
+    let upload_mask = move |_| {
+        spawn_local(async move {
+            let loaded = read_image_file(mask_input.get()).await.and_then(|source| {
+                let Some(source) = source else { return Ok(None) };
+                let (height, width, _) = source.image.dim();
+                match size.get_untracked() {
+                    Some(size) if size == (height, width) => Ok(Some(source.image)),
+                    _ => Err(AppError::InvalidMask(format!("{}x{} pixels, not the size of the image", width, height))),
+                }
+            });
+            let brightness = match loaded {
+                Ok(Some(mask)) => mask.mean_axis(Axis(2)).unwrap(),
+                Ok(None) => return,
+                Err(err) => {
+                    set_error.set(Some(err));
+                    return;
+                }
+            };
+            let (height, width) = brightness.dim();
+            let rgba: Vec<u8> = brightness.iter()
+                .flat_map(|&value| if value > 127.5 { [255, 0, 0, 255] } else { [0; 4] })
+                .collect();
+            let drawn = ImageData::new_with_u8_clamped_array_and_sh(Clamped(&rgba), width as u32, height as u32)
+                .and_then(|data| context(&canvas).map_or(Ok(()), |context| context.put_image_data(&data, 0., 0.)));
+            match drawn {
+                Ok(()) => publish(),
+                Err(e) => set_error.set(Some(AppError::InvalidMask(format!("{:?}", e)))),
+            }
+        })
+    };
+
+    view! {
+        <div class="mask-painter">
+            <div style="display: flex; align-items: center; gap: 8px;">
+                <label style="width: 5em;">"brush"</label>
+                <input
+                    type="range"
+                    min="1"
+                    max="64"
+                    step="1"
+                    prop:value=move || brush_size.get().to_string()
+                    on:input=move |ev| set_brush_size.set(event_target_value(&ev).parse().unwrap_or(brush_size.get()))
+                />
+                <label>
+                    <input type="checkbox" prop:checked=erase on:change=move |ev| set_erase.set(event_target_checked(&ev)) />
+                    "erase"
+                </label>
+                <button on:click=move |_| clear()>"Clear mask"</button>
+                <label>"load mask "</label>
+                <input type="file" accept="image/*" node_ref=mask_input on:change=upload_mask />
+            </div>
+            <div style="position: relative; display: inline-block; max-width: 100%;">
+                <img src=image alt="Mask" draggable="false" style="display: block; max-width: 100%;" />
+                <canvas
+                    node_ref=canvas
+                    width=move || size.get().map_or(0, |(_, width)| width)
+                    height=move || size.get().map_or(0, |(height, _)| height)
+                    style="position: absolute; inset: 0; width: 100%; height: 100%; opacity: 0.5; touch-action: none; cursor: crosshair;"
+                    on:pointerdown=on_pointer_down
+                    on:pointermove=on_pointer_move
+                    on:pointerup=on_pointer_up
+                    on:pointercancel=on_pointer_up
+                ></canvas>
+            </div>
+        </div>
+    }
+}
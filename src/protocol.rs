@@ -0,0 +1,99 @@
+// Message protocol between the App (main thread) and the denoising worker.
+//
+// Every message is a JS object `{ header, pixels }`: `header` is one of the
+// serde types below, converted with serde-wasm-bindgen, and `pixels` is an
+// optional Float32Array holding a row-major (height, width, channels) image.
+// The pixel buffer is transferred rather than copied when the message is posted.
+
+use ndarray::{Array2, Array3};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use web_sys::{js_sys, wasm_bindgen::JsValue};
+
+use tgv::{ColorMode, Kernel, TgvParams, TgvReport};
+
+// Main thread -> worker
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Request {
+    // Denoise the image sent along in `pixels`, and deblur it if `kernel` is set,
+    // sending a preview of the intermediate result every `preview_interval`
+    // iterations if set. With `inpaint`, the image in `pixels` is followed by the
+    // (height, width) mask of the pixels to fill in, 1 where missing and 0 elsewhere
+    Denoise {
+        job: u32,
+        height: usize,
+        width: usize,
+        channels: usize,
+        params: TgvParams,
+        color_mode: ColorMode,
+        kernel: Option<Kernel>,
+        inpaint: bool,
+        preview_interval: Option<usize>,
+    },
+}
+
+// Worker -> main thread
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Response {
+    // The worker has loaded its wasm module and is listening for requests
+    Ready,
+    // `done` out of `total` solver iterations have completed
+    Progress { job: u32, done: usize, total: usize },
+    // The intermediate result after `done` iterations is sent along in `pixels`
+    Preview { job: u32, done: usize, height: usize, width: usize, channels: usize },
+    // The denoised image is sent along in `pixels`, `reports` has one entry per solver run
+    Done { job: u32, height: usize, width: usize, channels: usize, reports: Vec<TgvReport> },
+    Failed { job: u32, message: String },
+}
+
+// Builds the message object and the list of buffers to transfer with it
+pub fn encode<T: Serialize>(header: &T, pixels: Option<&[f32]>) -> Result<(JsValue, js_sys::Array), String> {
+    let message = js_sys::Object::new();
+    let transfer = js_sys::Array::new();
+
+    let header = serde_wasm_bindgen::to_value(header)
+        .map_err(|e| format!("Failed to serialize message: {:?}", e))?;
+    js_sys::Reflect::set(&message, &"header".into(), &header)
+        .map_err(|e| format!("Failed to build message: {:?}", e))?;
+
+    if let Some(pixels) = pixels {
+        let pixels = js_sys::Float32Array::from(pixels);
+        transfer.push(&pixels.buffer());
+        js_sys::Reflect::set(&message, &"pixels".into(), &pixels)
+            .map_err(|e| format!("Failed to build message: {:?}", e))?;
+    }
+
+    Ok((message.into(), transfer))
+}
+
+// Splits the (height, width) missing pixel mask off the end of the `pixels` of an
+// inpainting request
+pub fn split_mask(height: usize, width: usize, pixels: &mut Option<Vec<f32>>) -> Result<Array2<bool>, String> {
+    let pixels = pixels.as_mut().ok_or("Message without pixels")?;
+    let start = pixels.len().checked_sub(height * width).ok_or("Message without a mask")?;
+    let mask = pixels.split_off(start).into_iter().map(|missing| missing != 0.).collect();
+    Array2::from_shape_vec((height, width), mask).map_err(|e| format!("Invalid mask buffer: {:?}", e))
+}
+
+// Rebuilds the (height, width, channels) image from the `pixels` of a message
+pub fn pixels_to_array(height: usize, width: usize, channels: usize, pixels: Option<Vec<f32>>) -> Result<Array3<f32>, String> {
+    let pixels = pixels.ok_or("Message without pixels")?;
+    Array3::from_shape_vec((height, width, channels), pixels)
+        .map_err(|e| format!("Invalid image buffer: {:?}", e))
+}
+
+pub fn decode<T: DeserializeOwned>(message: &JsValue) -> Result<(T, Option<Vec<f32>>), String> {
+    let header = js_sys::Reflect::get(message, &"header".into())
+        .map_err(|e| format!("Malformed message: {:?}", e))?;
+    let header = serde_wasm_bindgen::from_value(header)
+        .map_err(|e| format!("Failed to deserialize message: {:?}", e))?;
+
+    let pixels = js_sys::Reflect::get(message, &"pixels".into())
+        .map_err(|e| format!("Malformed message: {:?}", e))?;
+    let pixels = if pixels.is_undefined() {
+        None
+    } else {
+        Some(js_sys::Float32Array::from(pixels).to_vec())
+    };
+
+    Ok((header, pixels))
+}
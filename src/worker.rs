@@ -0,0 +1,85 @@
+// Worker side of the denoising protocol. Runs inside a dedicated Web Worker
+// (see src/bin/worker.rs), so the solver never blocks the UI thread.
+
+use std::ops::ControlFlow;
+
+use web_sys::{
+    js_sys,
+    wasm_bindgen::{closure::Closure, JsCast},
+    DedicatedWorkerGlobalScope, MessageEvent,
+};
+
+use crate::protocol::{self, Request, Response};
+
+// Post a progress message every this many iterations
+const PROGRESS_INTERVAL: usize = 10;
+
+fn post(scope: &DedicatedWorkerGlobalScope, response: &Response, pixels: Option<&[f32]>) {
+    match protocol::encode(response, pixels) {
+        Ok((message, transfer)) => {
+            if let Err(e) = scope.post_message_with_transfer(&message, &transfer) {
+                web_sys::console::error_1(&e);
+            }
+        }
+        Err(e) => web_sys::console::error_1(&e.into()),
+    }
+}
+
+fn handle_request(scope: &DedicatedWorkerGlobalScope, request: Request, mut pixels: Option<Vec<f32>>) -> Result<(), (u32, String)> {
+    match request {
+        Request::Denoise { job, height, width, channels, params, color_mode, kernel, inpaint, preview_interval } => {
+            let missing = inpaint.then(|| protocol::split_mask(height, width, &mut pixels)).transpose().map_err(|e| (job, e))?;
+            let u0 = protocol::pixels_to_array(height, width, channels, pixels).map_err(|e| (job, e))?;
+            if channels != 3 {
+                return Err((job, format!("Expected an RGB image, got {} channels", channels)));
+            }
+            let valid = if kernel.is_some() { params.validate_for_deblurring() } else { params.validate() };
+            valid.map_err(|e| (job, e.to_string()))?;
+
+            let on_progress = |progress: &tgv::Progress| {
+                let (done, total) = (progress.done, progress.total);
+                if done % PROGRESS_INTERVAL == 0 || done == total {
+                    post(scope, &Response::Progress { job, done, total }, None);
+                }
+                // No preview of the final iterate, it is sent with Done anyway
+                if preview_interval.is_some_and(|interval| done % interval == 0) && done < total {
+                    let preview = progress.snapshot().into_raw_vec_and_offset().0;
+                    post(scope, &Response::Preview { job, done, height, width, channels }, Some(&preview));
+                }
+                // The solver blocks this worker, so the main thread cancels a job by
+                // terminating the worker instead
+                ControlFlow::Continue(())
+            };
+            let degradation = tgv::Degradation { blur: kernel.as_ref(), missing: missing.as_ref().map(|missing| missing.view()) };
+            let (denoised, reports) = tgv::tgv_restore_color_with_report(&u0.view(), &degradation, color_mode, &params, on_progress)
+                .map_err(|e| (job, e.to_string()))?;
+
+            // Both solvers return a standard layout array, so this never copies
+            let denoised = denoised.into_raw_vec_and_offset().0;
+            post(scope, &Response::Done { job, height, width, channels, reports }, Some(&denoised));
+            Ok(())
+        }
+    }
+}
+
+// Installs the message handler and tells the main thread we are ready
+pub fn run() {
+    let scope: DedicatedWorkerGlobalScope = js_sys::global().unchecked_into();
+
+    let handler_scope = scope.clone();
+    let on_message = Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+        match protocol::decode::<Request>(&event.data()) {
+            Ok((request, pixels)) => {
+                if let Err((job, message)) = handle_request(&handler_scope, request, pixels) {
+                    post(&handler_scope, &Response::Failed { job, message }, None);
+                }
+            }
+            Err(e) => web_sys::console::error_1(&e.into()),
+        }
+    });
+    scope.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+    // The handler lives as long as the worker
+    on_message.forget();
+
+    post(&scope, &Response::Ready, None);
+}
@@ -0,0 +1,237 @@
+use std::sync::Arc;
+
+use ndarray::{Array2, ArrayView2, ArrayViewMut2};
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::params::TgvError;
+
+// Kernels with at most this many weights are applied directly, larger ones by FFT
+const MAX_DIRECT_WEIGHTS: usize = 81;
+
+/// Largest number of rows or columns of a [`Kernel::gaussian`] or [`Kernel::motion`].
+pub const MAX_KERNEL_SIZE: usize = 1025;
+
+// Radius of a kernel reaching `extent` pixels from its center, at most
+// MAX_KERNEL_SIZE / 2
+fn kernel_radius(extent: f32) -> Result<usize, TgvError> {
+    if !extent.is_finite() {
+        return Err(TgvError::InvalidKernel("the blur size must be finite"));
+    }
+    let radius = extent.ceil();
+    if radius > (MAX_KERNEL_SIZE / 2) as f32 {
+        return Err(TgvError::InvalidKernel("the blur is larger than the maximum kernel size"));
+    }
+    Ok(radius as usize)
+}
+
+/// A blur kernel (point spread function) for [`tgv_deblur`](crate::tgv_deblur).
+///
+/// The weights are non-negative and sum to one, so the blur keeps the brightness of
+/// the image. The center of the kernel is the pixel at `(rows / 2, columns / 2)`.
+/// The blur wraps around the image borders.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Kernel {
+    shape: (usize, usize),
+    // Row major
+    weights: Vec<f32>,
+}
+
+impl Kernel {
+    /// Normalizes `weights` to sum to one. Fails if a weight is negative or not
+    /// finite, or if they are all zero.
+    pub fn new(weights: &ArrayView2<f32>) -> Result<Kernel, TgvError> {
+        if weights.iter().any(|w| !w.is_finite() || *w < 0.) {
+            return Err(TgvError::InvalidKernel("weights must be finite and non-negative"));
+        }
+        let sum: f32 = weights.sum();
+        if sum <= 0. {
+            return Err(TgvError::InvalidKernel("weights must not all be zero"));
+        }
+        Ok(Kernel { shape: weights.dim(), weights: weights.iter().map(|w| w / sum).collect() })
+    }
+
+    /// Gaussian blur with standard deviation `sigma` in pixels, cut off at three
+    /// standard deviations. Fails if the kernel would have more than
+    /// [`MAX_KERNEL_SIZE`] rows.
+    pub fn gaussian(sigma: f32) -> Result<Kernel, TgvError> {
+        if sigma.is_nan() || sigma <= 0. {
+            return Err(TgvError::NotPositive { name: "blur sigma", value: sigma });
+        }
+        let radius = kernel_radius(3. * sigma)?;
+        let weights = Array2::from_shape_fn((2 * radius + 1, 2 * radius + 1), |(i, j)| {
+            let (y, x) = (i as f32 - radius as f32, j as f32 - radius as f32);
+            (-(x * x + y * y) / (2. * sigma * sigma)).exp()
+        });
+        Kernel::new(&weights.view())
+    }
+
+    /// Linear motion blur over `length` pixels, in the direction `angle` in degrees
+    /// counterclockwise from the x axis. Fails if the kernel would have more than
+    /// [`MAX_KERNEL_SIZE`] rows.
+    pub fn motion(length: f32, angle: f32) -> Result<Kernel, TgvError> {
+        if length.is_nan() || length <= 0. {
+            return Err(TgvError::NotPositive { name: "blur length", value: length });
+        }
+        let radius = kernel_radius(length / 2.)?;
+        let size = 2 * radius + 1;
+        let (sin, cos) = angle.to_radians().sin_cos();
+        // Spread samples along the line over their nearest pixels
+        let samples = 4 * size;
+        let mut weights = Array2::zeros((size, size));
+        for s in 0..samples {
+            let t = length * ((s as f32 + 0.5) / samples as f32 - 0.5);
+            // Rows grow downwards
+            let (i, j) = ((radius as f32 - t * sin).round() as usize, (radius as f32 + t * cos).round() as usize);
+            weights[[i, j]] += 1.;
+        }
+        Kernel::new(&weights.view())
+    }
+
+    /// Number of rows and columns of the kernel.
+    pub fn shape(&self) -> (usize, usize) {
+        self.shape
+    }
+
+    /// The normalized weights.
+    pub fn weights(&self) -> ArrayView2<'_, f32> {
+        ArrayView2::from_shape(self.shape, &self.weights).unwrap()
+    }
+
+    /// Blurs a single channel image with this kernel.
+    pub fn convolve(&self, u: &ArrayView2<f32>) -> Array2<f32> {
+        let mut out = Array2::zeros(u.dim());
+        Convolution::new(self, u.dim()).apply(u, &mut out.view_mut(), false);
+        out
+    }
+}
+
+// Offsets of the nonzero kernel weights from the center, with the weights
+fn offsets(kernel: &Kernel) -> Vec<(isize, isize, f32)> {
+    let (rows, columns) = kernel.shape;
+    kernel.weights().indexed_iter().filter(|(_, w)| **w != 0.).map(|((i, j), &w)| {
+        (i as isize - (rows / 2) as isize, j as isize - (columns / 2) as isize, w)
+    }).collect()
+}
+
+// Periodic convolution with a kernel, for images of a fixed size
+pub(crate) enum Convolution {
+    Direct(Vec<(isize, isize, f32)>),
+    Fft(Box<FftConvolution>),
+}
+
+impl Convolution {
+    pub(crate) fn new(kernel: &Kernel, dim: (usize, usize)) -> Self {
+        if kernel.weights.len() <= MAX_DIRECT_WEIGHTS {
+            Convolution::Direct(offsets(kernel))
+        } else {
+            Convolution::Fft(Box::new(FftConvolution::new(kernel, dim)))
+        }
+    }
+
+    // Writes the blurred `u` to `out`, or with `adjoint` the correlation with the
+    // kernel, which is the adjoint of the blur
+    pub(crate) fn apply(&mut self, u: &ArrayView2<f32>, out: &mut ArrayViewMut2<f32>, adjoint: bool) {
+        match self {
+            Convolution::Direct(offsets) => {
+                let (height, width) = u.dim();
+                let sign = if adjoint { -1 } else { 1 };
+                let wrap = |x: isize, n: usize| x.rem_euclid(n as isize) as usize;
+                for ((i, j), out) in out.indexed_iter_mut() {
+                    *out = offsets.iter().map(|&(di, dj, w)| {
+                        w * u[[wrap(i as isize - sign * di, height), wrap(j as isize - sign * dj, width)]]
+                    }).sum();
+                }
+            }
+            Convolution::Fft(fft) => fft.apply(u, out, adjoint),
+        }
+    }
+}
+
+// Convolution as a pointwise product of 2D spectra. The 2D transforms run along the
+// rows, then along the rows of the transposed image, so the spectra are transposed
+pub(crate) struct FftConvolution {
+    height: usize,
+    width: usize,
+    row_fft: Arc<dyn Fft<f32>>,
+    row_ifft: Arc<dyn Fft<f32>>,
+    column_fft: Arc<dyn Fft<f32>>,
+    column_ifft: Arc<dyn Fft<f32>>,
+    spectrum: Vec<Complex<f32>>,
+    image: Vec<Complex<f32>>,
+    transposed: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+}
+
+// Writes the transpose of the (rows, columns) row major `src` to `dst`
+fn transpose(src: &[Complex<f32>], dst: &mut [Complex<f32>], rows: usize, columns: usize) {
+    for i in 0..rows {
+        for j in 0..columns {
+            dst[j * rows + i] = src[i * columns + j];
+        }
+    }
+}
+
+impl FftConvolution {
+    fn new(kernel: &Kernel, (height, width): (usize, usize)) -> Self {
+        let mut planner = FftPlanner::new();
+        let (row_fft, row_ifft) = (planner.plan_fft_forward(width), planner.plan_fft_inverse(width));
+        let (column_fft, column_ifft) = (planner.plan_fft_forward(height), planner.plan_fft_inverse(height));
+        let scratch_len = [&row_fft, &row_ifft, &column_fft, &column_ifft].iter()
+            .map(|fft| fft.get_inplace_scratch_len())
+            .max()
+            .unwrap();
+        let mut convolution = FftConvolution {
+            height,
+            width,
+            row_fft,
+            row_ifft,
+            column_fft,
+            column_ifft,
+            spectrum: Vec::new(),
+            image: vec![Complex::default(); height * width],
+            transposed: vec![Complex::default(); height * width],
+            scratch: vec![Complex::default(); scratch_len],
+        };
+
+        // The kernel wrapped around the origin of an image sized array
+        for (di, dj, w) in offsets(kernel) {
+            let (i, j) = (di.rem_euclid(height as isize) as usize, dj.rem_euclid(width as isize) as usize);
+            convolution.image[i * width + j].re += w;
+        }
+        convolution.forward();
+        convolution.spectrum = convolution.transposed.clone();
+        convolution
+    }
+
+    // 2D transform of `image` into `transposed`
+    fn forward(&mut self) {
+        self.row_fft.process_with_scratch(&mut self.image, &mut self.scratch);
+        transpose(&self.image, &mut self.transposed, self.height, self.width);
+        self.column_fft.process_with_scratch(&mut self.transposed, &mut self.scratch);
+    }
+
+    // Inverse 2D transform of `transposed` into `image`, unnormalized
+    fn inverse(&mut self) {
+        self.column_ifft.process_with_scratch(&mut self.transposed, &mut self.scratch);
+        transpose(&self.transposed, &mut self.image, self.width, self.height);
+        self.row_ifft.process_with_scratch(&mut self.image, &mut self.scratch);
+    }
+
+    fn apply(&mut self, u: &ArrayView2<f32>, out: &mut ArrayViewMut2<f32>, adjoint: bool) {
+        for (pixel, &u) in self.image.iter_mut().zip(u.iter()) {
+            *pixel = Complex::new(u, 0.);
+        }
+        self.forward();
+        for (x, k) in self.transposed.iter_mut().zip(&self.spectrum) {
+            *x *= if adjoint { k.conj() } else { *k };
+        }
+        self.inverse();
+        let scale = 1. / (self.height * self.width) as f32;
+        for (out, pixel) in out.iter_mut().zip(&self.image) {
+            *out = pixel.re * scale;
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use std::ops::ControlFlow;
+
+use ndarray::{s, Array2, Array3, ArrayView3, Axis};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::data_term::DataTerm;
+use crate::params::{TgvError, TgvParams};
+use crate::solver::{solve, Degradation, TgvReport};
+
+/// How the channels of a color image are denoised.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ColorMode {
+    /// Average the channels and denoise the resulting gray image.
+    Grayscale,
+    /// Vectorial TGV on RGB, with the channels coupled.
+    Vectorial,
+    /// Independent scalar TGV on each of R, G and B.
+    PerChannel,
+    /// Independent scalar TGV on each of Y, Cb and Cr.
+    YCbCr,
+}
+
+impl ColorMode {
+    pub const ALL: [ColorMode; 4] = [ColorMode::Grayscale, ColorMode::Vectorial, ColorMode::PerChannel, ColorMode::YCbCr];
+
+    /// Human readable name, e.g. for a UI.
+    pub fn label(&self) -> &'static str {
+        match self {
+            ColorMode::Grayscale => "Grayscale",
+            ColorMode::Vectorial => "Color (coupled RGB)",
+            ColorMode::PerChannel => "Color (per channel)",
+            ColorMode::YCbCr => "Color (YCbCr)",
+        }
+    }
+
+    /// Checks that the data term of `params` can be used in this mode. The KL data
+    /// term needs non-negative counts, so it cannot run on the signed Cb and Cr
+    /// channels of YCbCr.
+    pub fn validate(self, params: &TgvParams) -> Result<(), TgvError> {
+        if self == ColorMode::YCbCr && params.data_term == DataTerm::Kl {
+            return Err(TgvError::UnsupportedColorMode { data_term: params.data_term, mode: self });
+        }
+        Ok(())
+    }
+}
+
+// Full range (JPEG) RGB <-> YCbCr conversion matrices
+const RGB_TO_YCBCR: [[f32; 3]; 3] = [
+    [0.299, 0.587, 0.114],
+    [-0.168736, -0.331264, 0.5],
+    [0.5, -0.418688, -0.081312],
+];
+const YCBCR_TO_RGB: [[f32; 3]; 3] = [
+    [1.0, 0.0, 1.402],
+    [1.0, -0.344136, -0.714136],
+    [1.0, 1.772, 0.0],
+];
+
+// Applies a 3x3 color matrix to every pixel of an (h, w, 3) image
+fn transform_colors(img: &ArrayView3<f32>, matrix: &[[f32; 3]; 3]) -> Array3<f32> {
+    let matrix = Array2::from_shape_fn((3, 3), |(i, j)| matrix[i][j]);
+    let (height, width, _) = img.dim();
+    let pixels = img.to_shape((height * width, 3)).unwrap();
+    pixels.dot(&matrix.t()).into_shape_with_order((height, width, 3)).unwrap()
+}
+
+/// Progress of [`tgv_denoise_color_with_report`], passed to its callback after every
+/// iteration.
+pub struct Progress<'a> {
+    /// Completed iterations, counted over all channels.
+    pub done: usize,
+    /// Total number of iterations over all channels. Fewer may be needed if the
+    /// solver converges early.
+    pub total: usize,
+    snapshot: &'a dyn Fn() -> Array3<f32>,
+}
+
+impl Progress<'_> {
+    /// The current estimate of the denoised RGB image. Channels that have not been
+    /// processed yet are still noisy.
+    ///
+    /// This copies the whole image, so call it only every few iterations.
+    pub fn snapshot(&self) -> Array3<f32> {
+        (self.snapshot)()
+    }
+}
+
+// Denoises the channels of `u0` independently, reporting progress over all channels.
+// `to_rgb` converts the channels back to RGB for the snapshots. After a cancellation
+// the remaining channels are left as they are in `u0`
+fn per_channel_denoise(
+    u0: &ArrayView3<f32>,
+    params: &TgvParams,
+    degradation: &Degradation,
+    to_rgb: impl Fn(Array3<f32>) -> Array3<f32>,
+    mut on_progress: impl FnMut(&Progress) -> ControlFlow<()>,
+) -> (Array3<f32>, Vec<TgvReport>) {
+    let n_iter = params.n_iter;
+    let channels = u0.shape()[2];
+    let total = n_iter * channels;
+    let mut denoised = u0.to_owned();
+    let mut reports = Vec::with_capacity(channels);
+    for c in 0..channels {
+        let (denoised_c, report) = solve(&u0.slice(s![.., .., c..=c]), params, degradation, |i, u| {
+            let snapshot = || {
+                let mut snapshot = denoised.clone();
+                snapshot.slice_mut(s![.., .., c..=c]).assign(u);
+                to_rgb(snapshot)
+            };
+            on_progress(&Progress { done: c * n_iter + i, total, snapshot: &snapshot })
+        });
+        denoised.slice_mut(s![.., .., c..=c]).assign(&denoised_c);
+        let cancelled = report.cancelled;
+        reports.push(report);
+        if cancelled {
+            break;
+        }
+    }
+    (denoised, reports)
+}
+
+/// Denoises an RGB image of shape `(h, w, 3)` according to `mode`.
+///
+/// The result is always an `(h, w, 3)` RGB image. `on_progress` receives the number of
+/// completed iterations and the total number of iterations over all channels, and
+/// cancels the run by returning [`ControlFlow::Break`].
+///
+/// Fails if `mode` cannot be used with `params`, see [`ColorMode::validate`].
+pub fn tgv_denoise_color(u0: &ArrayView3<f32>, mode: ColorMode, params: &TgvParams, mut on_progress: impl FnMut(usize, usize) -> ControlFlow<()>) -> Result<Array3<f32>, TgvError> {
+    tgv_denoise_color_with_report(u0, mode, params, |progress| on_progress(progress.done, progress.total)).map(|(denoised, _)| denoised)
+}
+
+// Repeats a single channel image into the three RGB channels
+fn gray_to_rgb(gray: &ArrayView3<f32>) -> Array3<f32> {
+    ndarray::concatenate![Axis(2), *gray, *gray, *gray]
+}
+
+/// Same as [`tgv_denoise_color`], but also returns the report of every solver run:
+/// one for the grayscale and vectorial modes, one per channel for the others.
+///
+/// `on_progress` gets a [`Progress`], which can also produce snapshots of the
+/// intermediate result.
+pub fn tgv_denoise_color_with_report(u0: &ArrayView3<f32>, mode: ColorMode, params: &TgvParams, on_progress: impl FnMut(&Progress) -> ControlFlow<()>) -> Result<(Array3<f32>, Vec<TgvReport>), TgvError> {
+    tgv_restore_color_with_report(u0, &Degradation::default(), mode, params, on_progress)
+}
+
+/// Same as [`tgv_denoise_color_with_report`], but also undoes the `degradation` of
+/// every channel, see [`tgv_restore`](crate::tgv_restore).
+// The blur and the missing pixels commute with the color transforms, which are the
+// same for every pixel
+pub fn tgv_restore_color_with_report(u0: &ArrayView3<f32>, degradation: &Degradation, mode: ColorMode, params: &TgvParams, on_progress: impl FnMut(&Progress) -> ControlFlow<()>) -> Result<(Array3<f32>, Vec<TgvReport>), TgvError> {
+    mode.validate(params)?;
+    Ok(restore_color(u0, degradation, mode, params, on_progress))
+}
+
+// `tgv_restore_color_with_report` for a `mode` already validated against `params`
+pub(crate) fn restore_color(u0: &ArrayView3<f32>, degradation: &Degradation, mode: ColorMode, params: &TgvParams, mut on_progress: impl FnMut(&Progress) -> ControlFlow<()>) -> (Array3<f32>, Vec<TgvReport>) {
+    let n_iter = params.n_iter;
+    assert_eq!(u0.shape()[2], 3, "tgv_denoise_color expects an RGB image");
+    match mode {
+        ColorMode::Grayscale => {
+            let grayscale = u0.mean_axis(Axis(2)).unwrap().insert_axis(Axis(2));
+            let (denoised, report) = solve(&grayscale.view(), params, degradation, |i, u| {
+                on_progress(&Progress { done: i, total: n_iter, snapshot: &|| gray_to_rgb(u) })
+            });
+            (gray_to_rgb(&denoised.view()), vec![report])
+        }
+        ColorMode::Vectorial => {
+            let (denoised, report) = solve(u0, params, degradation, |i, u| {
+                on_progress(&Progress { done: i, total: n_iter, snapshot: &|| u.to_owned() })
+            });
+            (denoised, vec![report])
+        }
+        ColorMode::PerChannel => {
+            per_channel_denoise(u0, params, degradation, |rgb| rgb, on_progress)
+        }
+        ColorMode::YCbCr => {
+            let ycbcr = transform_colors(u0, &RGB_TO_YCBCR);
+            let to_rgb = |ycbcr: Array3<f32>| transform_colors(&ycbcr.view(), &YCBCR_TO_RGB);
+            let (denoised, reports) = per_channel_denoise(&ycbcr.view(), params, degradation, to_rgb, on_progress);
+            (to_rgb(denoised), reports)
+        }
+    }
+}
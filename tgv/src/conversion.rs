@@ -0,0 +1,138 @@
+//! Conversions between `image` buffers and the `(height, width, 3)` arrays used by the
+//! solvers.
+//!
+//! Arrays are always on the 0..255 scale of 8-bit images, whatever the depth of the
+//! source, so that `lambda` means the same for every input. 16-bit and floating point
+//! images are converted without quantizing, and [`BitDepth`] remembers the depth to
+//! write the result back at. On the way out, [`OutputMapping`] optionally rescales the
+//! values, and integer samples are rounded and clamped to their range.
+
+use image::{ColorType, DynamicImage, ImageBuffer, Rgb};
+use ndarray::{Array3, ArrayView3};
+
+/// Sample depth of an image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BitDepth {
+    Eight,
+    Sixteen,
+    /// 32-bit floating point, nominally in 0..1.
+    Float,
+}
+
+impl BitDepth {
+    /// The depth `img` is stored at.
+    pub fn of(img: &DynamicImage) -> BitDepth {
+        match img.color() {
+            ColorType::L16 | ColorType::La16 | ColorType::Rgb16 | ColorType::Rgba16 => BitDepth::Sixteen,
+            ColorType::Rgb32F | ColorType::Rgba32F => BitDepth::Float,
+            _ => BitDepth::Eight,
+        }
+    }
+
+    /// Sample values per unit of the 0..255 scale of the arrays. If the samples are
+    /// photon counts, this is the [`TgvParams::count_scale`](crate::TgvParams::count_scale)
+    /// of the KL data term.
+    pub fn counts_per_unit(self) -> f32 {
+        1. / self.scale()
+    }
+
+    // Factor from the sample values to the 0..255 scale
+    fn scale(self) -> f32 {
+        match self {
+            BitDepth::Eight => 1.,
+            BitDepth::Sixteen => 255. / 65535.,
+            BitDepth::Float => 255.,
+        }
+    }
+}
+
+/// How denoised values are mapped to the 0..255 range before writing them out.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutputMapping {
+    /// Keep the values, clamped to 0..255.
+    #[default]
+    Clamp,
+    /// Stretch the values between the `low` and `high` percentiles (in 0..100) to the
+    /// full range, clamping the rest. All channels share one range, so colors keep
+    /// their balance. Meant for display, as it changes the intensities.
+    Percentile { low: f32, high: f32 },
+}
+
+impl OutputMapping {
+    /// Applies the mapping to an image on the 0..255 scale.
+    pub fn apply(&self, img: &ArrayView3<f32>) -> Array3<f32> {
+        let (offset, gain) = match *self {
+            OutputMapping::Clamp => (0., 1.),
+            OutputMapping::Percentile { low, high } => {
+                let low = percentile(img, low);
+                let high = percentile(img, high);
+                // A flat image has nothing to stretch
+                if high > low { (low, 255. / (high - low)) } else { (0., 1.) }
+            }
+        };
+        img.mapv(|x| ((x - offset) * gain).clamp(0., 255.))
+    }
+}
+
+// Value below which `p` percent of the samples of `img` lie, NaN for an empty image
+fn percentile(img: &ArrayView3<f32>, p: f32) -> f32 {
+    let mut values: Vec<f32> = img.iter().copied().filter(|x| !x.is_nan()).collect();
+    if values.is_empty() {
+        return f32::NAN;
+    }
+    let rank = (p.clamp(0., 100.) / 100. * (values.len() - 1) as f32).round() as usize;
+    *values.select_nth_unstable_by(rank, f32::total_cmp).1
+}
+
+// Rounds to the nearest integer sample in 0..=max, NaN becomes 0
+fn quantize(x: f32, max: f32) -> f32 {
+    x.round().clamp(0., max)
+}
+
+fn from_raw(width: u32, height: u32, data: Vec<f32>) -> Array3<f32> {
+    Array3::from_shape_vec((height as usize, width as usize, 3), data)
+        .expect("image buffers hold exactly width * height pixels")
+}
+
+/// Converts `img` to an RGB `(height, width, 3)` array on the 0..255 scale, and
+/// returns the depth it was stored at. Gray images are repeated into the three
+/// channels, alpha is dropped.
+pub fn image_to_array(img: &DynamicImage) -> (Array3<f32>, BitDepth) {
+    let depth = BitDepth::of(img);
+    let scale = depth.scale();
+    let (width, height) = (img.width(), img.height());
+    let data = match depth {
+        BitDepth::Eight => img.to_rgb8().into_raw().into_iter().map(f32::from).collect(),
+        BitDepth::Sixteen => img.to_rgb16().into_raw().into_iter().map(|x| x as f32 * scale).collect(),
+        BitDepth::Float => img.to_rgb32f().into_raw().into_iter().map(|x| x * scale).collect(),
+    };
+    (from_raw(width, height, data), depth)
+}
+
+/// Converts an RGB `(height, width, 3)` array on the 0..255 scale to an image at
+/// `depth`.
+///
+/// Integer samples are rounded to the nearest value and clamped to their range.
+/// Floating point samples are written as they are, out of range values included.
+pub fn array_to_image(img: &ArrayView3<f32>, depth: BitDepth) -> DynamicImage {
+    let (height, width, channels) = img.dim();
+    assert_eq!(channels, 3, "array_to_image expects an RGB image");
+    let (width, height) = (width as u32, height as u32);
+    let img = img.as_standard_layout();
+    let scale = depth.scale();
+    // The buffers are built from exactly width * height pixels
+    match depth {
+        BitDepth::Eight => {
+            let data = img.iter().map(|x| quantize(*x, 255.) as u8).collect();
+            DynamicImage::ImageRgb8(ImageBuffer::<Rgb<u8>, _>::from_raw(width, height, data).unwrap())
+        }
+        BitDepth::Sixteen => {
+            let data = img.iter().map(|x| quantize(x / scale, 65535.) as u16).collect();
+            DynamicImage::ImageRgb16(ImageBuffer::<Rgb<u16>, _>::from_raw(width, height, data).unwrap())
+        }
+        BitDepth::Float => {
+            let data = img.iter().map(|x| x / scale).collect();
+            DynamicImage::ImageRgb32F(ImageBuffer::<Rgb<f32>, _>::from_raw(width, height, data).unwrap())
+        }
+    }
+}
@@ -0,0 +1,86 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Data fidelity term `D(u, u0)` of the solver, which minimizes `D(u, u0) + R(u)`
+/// with `R` the regularizer, see [`TgvParams::data_term`](crate::TgvParams::data_term).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum DataTerm {
+    /// `1/2 ||u - u0||^2`, for Gaussian noise.
+    #[default]
+    L2,
+    /// `||u - u0||_1`, for impulse noise such as salt and pepper or dropouts. Keeps
+    /// the pixels that fit the regularizer exactly and replaces the outliers.
+    L1,
+    /// Kullback-Leibler divergence `s sum u - u0 + u0 log(u0 / u)`, the negative log
+    /// likelihood of Poisson noise, for photon-limited images. `s` is
+    /// [`TgvParams::count_scale`](crate::TgvParams::count_scale), the photon counts
+    /// per unit of the image: images are on the 0..255 scale, so the counts of a
+    /// 16-bit image need `s` = [`BitDepth::counts_per_unit`](crate::BitDepth::counts_per_unit).
+    /// Without it the noise is overestimated and the result oversmoothed. The result
+    /// is never negative, pixels with zero counts are allowed.
+    Kl,
+}
+
+impl DataTerm {
+    pub const ALL: [DataTerm; 3] = [DataTerm::L2, DataTerm::L1, DataTerm::Kl];
+
+    /// Human readable name, e.g. for a UI.
+    pub fn label(&self) -> &'static str {
+        match self {
+            DataTerm::L2 => "L2 (Gaussian noise)",
+            DataTerm::L1 => "L1 (impulse noise)",
+            DataTerm::Kl => "KL (Poisson noise)",
+        }
+    }
+
+    // Proximal step argmin_u |u - v|^2 / (2 tau) + D(u, u0) of a single pixel
+    pub(crate) fn prox(self, v: f32, u0: f32, tau: f32) -> f32 {
+        match self {
+            DataTerm::L2 => (v + tau * u0) / (1. + tau),
+            // Soft shrinkage of v towards u0
+            DataTerm::L1 => u0 + (v - u0).signum() * ((v - u0).abs() - tau).max(0.),
+            // Positive root of u^2 - (v - tau) u - tau u0 = 0, which is max(v - tau, 0)
+            // for zero counts
+            DataTerm::Kl => {
+                let b = v - tau;
+                0.5 * (b + (b * b + 4. * tau * u0.max(0.)).sqrt())
+            }
+        }
+    }
+
+    // Proximal step argmin_r |r - v|^2 / (2 sigma) + D*(r, u0) of the conjugate, for
+    // the dual variable of the data term when deblurring
+    pub(crate) fn dual_prox(self, v: f32, u0: f32, sigma: f32) -> f32 {
+        match self {
+            DataTerm::L2 => (v - sigma * u0) / (1. + sigma),
+            DataTerm::L1 => (v - sigma * u0).clamp(-1., 1.),
+            // Smaller root of r^2 - (1 + v) r + v - sigma u0 = 0, which keeps r <= 1
+            DataTerm::Kl => 0.5 * (1. + v - ((v - 1.).powi(2) + 4. * sigma * u0.max(0.)).sqrt()),
+        }
+    }
+
+    // D(u, u0) of a single pixel
+    pub(crate) fn energy(self, u: f64, u0: f64) -> f64 {
+        match self {
+            DataTerm::L2 => 0.5 * (u - u0).powi(2),
+            DataTerm::L1 => (u - u0).abs(),
+            DataTerm::Kl if u0 <= 0. => u,
+            DataTerm::Kl if u <= 0. => f64::INFINITY,
+            DataTerm::Kl => u - u0 + u0 * (u0 / u).ln(),
+        }
+    }
+
+    // Convex conjugate D*(z) of a single pixel. For L1 this leaves out the
+    // constraint |z| <= 1, and for KL with zero counts the constraint z <= 1, which
+    // the dual iterates only satisfy in the limit
+    pub(crate) fn conjugate(self, z: f64, u0: f64) -> f64 {
+        match self {
+            DataTerm::L2 => z * u0 + 0.5 * z.powi(2),
+            DataTerm::L1 => z * u0,
+            DataTerm::Kl if u0 <= 0. => 0.,
+            DataTerm::Kl if z >= 1. => f64::INFINITY,
+            DataTerm::Kl => -u0 * (1. - z).ln(),
+        }
+    }
+}
@@ -0,0 +1,59 @@
+//! Total generalized variation (TGV) denoising.
+//!
+//! Solves the second-order TGV-L2 model
+//!
+//! ```text
+//! min_u  1/2 ||u - u0||^2 + lambda * TGV(u),
+//! TGV(u) = min_w  alpha1 ||grad u - w||_1 + alpha0 ||E w||_1
+//! ```
+//!
+//! with the first-order primal-dual algorithm of Chambolle and Pock, as described
+//! in Bredies, Kunisch & Pock, "Total Generalized Variation" (2010). The same solver
+//! also runs with plain or Huber total variation in place of TGV, see
+//! [`Regularization`], or with any other [`Regularizer`], and with L1 or
+//! Kullback-Leibler (Poisson) data terms in place of the quadratic one, see
+//! [`DataTerm`]. [`tgv_deblur`] additionally removes a known blur [`Kernel`], and
+//! [`tgv_inpaint`] fills in missing pixels.
+//!
+//! Images are `ndarray` arrays indexed `(row, column)`, or `(row, column, channel)`
+//! for multi-channel images.
+//!
+//! ```
+//! use ndarray::Array2;
+//! use tgv::{tgv_denoise, TgvParams};
+//!
+//! let noisy = Array2::<f32>::from_elem((32, 48), 100.0);
+//! let params = TgvParams::builder().lambda(2.0).n_iter(50).build().unwrap();
+//! let denoised = tgv_denoise(&noisy.view(), &params);
+//! assert_eq!(denoised.dim(), (32, 48));
+//! ```
+
+mod blur;
+mod color;
+mod data_term;
+#[cfg(feature = "image")]
+mod conversion;
+pub mod operators;
+mod params;
+mod regularizer;
+mod solver;
+#[cfg(feature = "parallel")]
+mod tiling;
+
+pub use blur::{Kernel, MAX_KERNEL_SIZE};
+pub use color::{tgv_denoise_color, tgv_denoise_color_with_report, tgv_restore_color_with_report, ColorMode, Progress};
+pub use data_term::DataTerm;
+#[cfg(feature = "image")]
+pub use conversion::{array_to_image, image_to_array, BitDepth, OutputMapping};
+pub use operators::{
+    divergence, divergence_into, gradient, gradient_into, sym_divergence, sym_divergence_into, sym_gradient,
+    sym_gradient_into, Boundary,
+};
+pub use params::{TgvError, TgvParams, TgvParamsBuilder};
+pub use regularizer::{Regularization, Regularizer, Tgv, Tv};
+pub use solver::{
+    denoise_with_regularizer, tgv_deblur, tgv_denoise, tgv_denoise_with_progress, tgv_inpaint, tgv_restore, vtgv_denoise,
+    vtgv_denoise_with_report, Degradation, Residuals, TgvReport,
+};
+#[cfg(feature = "parallel")]
+pub use tiling::{parallel_tgv_denoise, parallel_tgv_denoise_color, tiled_denoise, Blend, TileConfig};
@@ -0,0 +1,195 @@
+//! Finite-difference operators used by the TGV solver.
+//!
+//! All operators use forward differences, with the behaviour at the last row and
+//! column given by a [`Boundary`]. Vector fields store their components along the
+//! last axis: component 0 is the derivative along x (columns, axis 1), component 1
+//! the derivative along y (rows, axis 0).
+//!
+//! For every boundary mode the divergences are the exact negative adjoints of the
+//! gradients, i.e. `<gradient(u), p> = -<u, divergence(p)>` and
+//! `<sym_gradient(w), q> = -<w, sym_divergence(q)>`.
+
+use ndarray::{s, Array2, Array3, ArrayView1, ArrayView2, ArrayView3, ArrayViewMut1, ArrayViewMut2, ArrayViewMut3, Axis, Zip};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// How the forward differences treat the pixels past the image border.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Boundary {
+    /// The image wraps around, the last pixel is compared with the first one.
+    #[default]
+    Periodic,
+    /// Zero flux: the difference past the last pixel is zero.
+    Neumann,
+    /// The image is mirrored about the last pixel, so the difference past the last
+    /// pixel is the negated difference before it.
+    Symmetric,
+}
+
+impl Boundary {
+    pub const ALL: [Boundary; 3] = [Boundary::Periodic, Boundary::Neumann, Boundary::Symmetric];
+
+    /// Human readable name, e.g. for a UI.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Boundary::Periodic => "Periodic",
+            Boundary::Neumann => "Neumann (zero flux)",
+            Boundary::Symmetric => "Symmetric (mirror)",
+        }
+    }
+
+    /// Upper bound on `||K||^2` for the TGV operator `K(u, w) = (grad u - w, sym_grad w)`
+    /// built from the differences of this mode.
+    ///
+    /// With periodic and Neumann boundaries `||D||^2 <= 4` for a single forward
+    /// difference and the bound of Bredies, Kunisch & Pock (2010) applies. The mirror
+    /// boundary has `||D||^2 <= 2 + 2 sqrt(2)`, and the bound is scaled up accordingly.
+    pub fn operator_norm_squared(&self) -> f32 {
+        match self {
+            Boundary::Periodic | Boundary::Neumann => 12.0,
+            Boundary::Symmetric => 14.5,
+        }
+    }
+}
+
+// Adds weight * (a[i + 1] - a[i]) to out[i], for a single row or column
+fn forward_difference_1d(a: ArrayView1<f32>, mut out: ArrayViewMut1<f32>, boundary: Boundary, weight: f32) {
+    let n = a.len();
+    if n == 0 {
+        return;
+    }
+    for i in 0..n - 1 {
+        out[i] += weight * (a[i + 1] - a[i]);
+    }
+    out[n - 1] += weight * match boundary {
+        Boundary::Periodic => a[0] - a[n - 1],
+        Boundary::Neumann => 0.,
+        Boundary::Symmetric if n > 1 => a[n - 2] - a[n - 1],
+        Boundary::Symmetric => 0.,
+    };
+}
+
+// Negative adjoint of `forward_difference_1d` (a backward difference in the interior),
+// also accumulated into `out`
+fn backward_difference_1d(p: ArrayView1<f32>, mut out: ArrayViewMut1<f32>, boundary: Boundary, weight: f32) {
+    let n = p.len();
+    if n == 0 {
+        return;
+    }
+    match boundary {
+        Boundary::Periodic => {
+            out[0] += weight * (p[0] - p[n - 1]);
+            for i in 1..n {
+                out[i] += weight * (p[i] - p[i - 1]);
+            }
+        }
+        Boundary::Neumann | Boundary::Symmetric => {
+            if n == 1 {
+                return;
+            }
+            out[0] += weight * p[0];
+            for i in 1..n - 1 {
+                out[i] += weight * (p[i] - p[i - 1]);
+            }
+            out[n - 1] -= weight * p[n - 2];
+            if boundary == Boundary::Symmetric {
+                // The last difference a[n - 2] - a[n - 1] also involves these two pixels
+                out[n - 2] -= weight * p[n - 1];
+                out[n - 1] += weight * p[n - 1];
+            }
+        }
+    }
+}
+
+type Difference1d = fn(ArrayView1<f32>, ArrayViewMut1<f32>, Boundary, f32);
+
+// Applies a 1D difference to every lane of `a` along `axis`, accumulating into `out`
+fn add_along_axis(a: &ArrayView2<f32>, mut out: ArrayViewMut2<f32>, axis: usize, boundary: Boundary, weight: f32, difference: Difference1d) {
+    Zip::from(a.lanes(Axis(axis)))
+        .and(out.lanes_mut(Axis(axis)))
+        .for_each(|a, out| difference(a, out, boundary, weight));
+}
+
+fn add_forward_difference(a: &ArrayView2<f32>, out: ArrayViewMut2<f32>, axis: usize, boundary: Boundary, weight: f32) {
+    add_along_axis(a, out, axis, boundary, weight, forward_difference_1d)
+}
+
+fn add_backward_difference(a: &ArrayView2<f32>, out: ArrayViewMut2<f32>, axis: usize, boundary: Boundary, weight: f32) {
+    add_along_axis(a, out, axis, boundary, weight, backward_difference_1d)
+}
+
+/// Forward-difference gradient of a `(h, w)` image, returned as a `(h, w, 2)` field.
+pub fn gradient(u: &ArrayView2<f32>, boundary: Boundary) -> Array3<f32> {
+    let (height, width) = u.dim();
+    let mut out = Array3::zeros((height, width, 2));
+    gradient_into(u, &mut out.view_mut(), boundary);
+    out
+}
+
+/// Writes the gradient of `u` into `out` without allocating, see [`gradient`].
+pub fn gradient_into(u: &ArrayView2<f32>, out: &mut ArrayViewMut3<f32>, boundary: Boundary) {
+    out.fill(0.);
+    add_forward_difference(u, out.slice_mut(s![.., .., 0]), 1, boundary, 1.);
+    add_forward_difference(u, out.slice_mut(s![.., .., 1]), 0, boundary, 1.);
+}
+
+/// Divergence of a `(h, w, 2)` field, the negative adjoint of [`gradient`].
+pub fn divergence(p: &ArrayView3<f32>, boundary: Boundary) -> Array2<f32> {
+    let (height, width, _) = p.dim();
+    let mut out = Array2::zeros((height, width));
+    divergence_into(p, &mut out.view_mut(), boundary);
+    out
+}
+
+/// Writes the divergence of `p` into `out` without allocating, see [`divergence`].
+pub fn divergence_into(p: &ArrayView3<f32>, out: &mut ArrayViewMut2<f32>, boundary: Boundary) {
+    out.fill(0.);
+    add_backward_difference(&p.slice(s![.., .., 0]), out.view_mut(), 1, boundary, 1.);
+    add_backward_difference(&p.slice(s![.., .., 1]), out.view_mut(), 0, boundary, 1.);
+}
+
+/// Symmetrized gradient of a `(h, w, 2)` field, returned as the `(h, w, 3)` field
+/// `(dx w_0, dy w_1, (dy w_0 + dx w_1) / 2)`.
+pub fn sym_gradient(w: &ArrayView3<f32>, boundary: Boundary) -> Array3<f32> {
+    let (height, width, _) = w.dim();
+    let mut out = Array3::zeros((height, width, 3));
+    sym_gradient_into(w, &mut out.view_mut(), boundary);
+    out
+}
+
+/// Writes the symmetrized gradient of `w` into `out` without allocating, see [`sym_gradient`].
+pub fn sym_gradient_into(w: &ArrayView3<f32>, out: &mut ArrayViewMut3<f32>, boundary: Boundary) {
+    out.fill(0.);
+    let w0 = w.slice(s![.., .., 0]);
+    let w1 = w.slice(s![.., .., 1]);
+    // First diagonal: ∂x w_0
+    add_forward_difference(&w0, out.slice_mut(s![.., .., 0]), 1, boundary, 1.);
+    // Second diagonal: ∂y w_1
+    add_forward_difference(&w1, out.slice_mut(s![.., .., 1]), 0, boundary, 1.);
+    // Off-diagonals: 0.5*(∂y w_0 + ∂x w_1)
+    add_forward_difference(&w0, out.slice_mut(s![.., .., 2]), 0, boundary, 0.5);
+    add_forward_difference(&w1, out.slice_mut(s![.., .., 2]), 1, boundary, 0.5);
+}
+
+/// Divergence of a `(h, w, 3)` symmetric tensor field, the negative adjoint of [`sym_gradient`].
+pub fn sym_divergence(q: &ArrayView3<f32>, boundary: Boundary) -> Array3<f32> {
+    let (height, width, _) = q.dim();
+    let mut out = Array3::zeros((height, width, 2));
+    sym_divergence_into(q, &mut out.view_mut(), boundary);
+    out
+}
+
+/// Writes the divergence of `q` into `out` without allocating, see [`sym_divergence`].
+pub fn sym_divergence_into(q: &ArrayView3<f32>, out: &mut ArrayViewMut3<f32>, boundary: Boundary) {
+    out.fill(0.);
+    let q0 = q.slice(s![.., .., 0]);
+    let q1 = q.slice(s![.., .., 1]);
+    let q2 = q.slice(s![.., .., 2]);
+    // First component: ∂x q_0 + 0.5 ∂y q_2
+    add_backward_difference(&q0, out.slice_mut(s![.., .., 0]), 1, boundary, 1.);
+    add_backward_difference(&q2, out.slice_mut(s![.., .., 0]), 0, boundary, 0.5);
+    // Second component: ∂y q_1 + 0.5 ∂x q_2
+    add_backward_difference(&q1, out.slice_mut(s![.., .., 1]), 0, boundary, 1.);
+    add_backward_difference(&q2, out.slice_mut(s![.., .., 1]), 1, boundary, 0.5);
+}
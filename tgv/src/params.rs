@@ -0,0 +1,239 @@
+use std::fmt;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::color::ColorMode;
+use crate::data_term::DataTerm;
+use crate::operators::Boundary;
+use crate::regularizer::Regularization;
+
+/// Invalid solver parameters.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TgvError {
+    /// A parameter that must be strictly positive is zero, negative or NaN.
+    NotPositive { name: &'static str, value: f32 },
+    /// The step sizes violate the convergence condition `tau * sigma * L^2 <= 1`,
+    /// where `L^2` is `norm_squared`.
+    StepSizeTooLarge { tau: f32, sigma: f32, norm_squared: f32 },
+    /// The weights of a blur [`Kernel`](crate::Kernel) are unusable.
+    InvalidKernel(&'static str),
+    /// The data term cannot be used in this color mode, see [`ColorMode::validate`].
+    UnsupportedColorMode { data_term: DataTerm, mode: ColorMode },
+}
+
+impl fmt::Display for TgvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TgvError::NotPositive { name, value } => write!(f, "{} must be positive, got {}", name, value),
+            TgvError::StepSizeTooLarge { tau, sigma, norm_squared } => write!(
+                f,
+                "Step sizes too large: tau * sigma * L^2 = {:.3} > 1 (L^2 = {})",
+                tau * sigma * norm_squared,
+                norm_squared
+            ),
+            TgvError::InvalidKernel(reason) => write!(f, "Invalid blur kernel: {}", reason),
+            TgvError::UnsupportedColorMode { data_term, mode } => {
+                write!(f, "The {} data term cannot be used with the {} color mode", data_term.label(), mode.label())
+            }
+        }
+    }
+}
+
+impl std::error::Error for TgvError {}
+
+/// Parameters of the TGV solver.
+///
+/// `regularization` selects the regularizer, TGV by default. `lambda` scales the
+/// whole regularizer, `alpha1` and `alpha0` weight its first and second order terms;
+/// the total variation regularizers only use `alpha1`, and Huber TV is quadratic
+/// for gradients below `huber_epsilon`. `data_term` selects how the result is tied
+/// to the noisy image, L2 by default. `count_scale` is the number of photon counts per
+/// unit of the image for the KL data term, see [`DataTerm::Kl`]. `tau` and `sigma`
+/// are the primal and dual step sizes, and `boundary` selects the boundary handling
+/// of the finite differences.
+///
+/// The solver measures its residuals every `check_interval` iterations, see
+/// [`Residuals`](crate::Residuals). With a `tolerance` it stops as soon as both
+/// residuals are below it, otherwise it always runs `n_iter` iterations.
+/// `compute_gap` additionally evaluates the primal-dual gap at every check.
+///
+/// Use [`TgvParams::builder`] to get validated parameters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TgvParams {
+    pub lambda: f32,
+    pub alpha0: f32,
+    pub alpha1: f32,
+    pub tau: f32,
+    pub sigma: f32,
+    pub n_iter: usize,
+    pub boundary: Boundary,
+    pub tolerance: Option<f32>,
+    pub check_interval: usize,
+    pub compute_gap: bool,
+    pub regularization: Regularization,
+    pub huber_epsilon: f32,
+    pub data_term: DataTerm,
+    pub count_scale: f32,
+}
+
+impl Default for TgvParams {
+    fn default() -> Self {
+        TgvParams {
+            lambda: 1.0,
+            alpha0: 2.0,
+            alpha1: 1.0,
+            tau: 0.125,
+            sigma: 0.125,
+            n_iter: 300,
+            boundary: Boundary::default(),
+            tolerance: None,
+            check_interval: 10,
+            compute_gap: false,
+            regularization: Regularization::default(),
+            huber_epsilon: 1.0,
+            data_term: DataTerm::default(),
+            count_scale: 1.0,
+        }
+    }
+}
+
+impl TgvParams {
+    /// Starts from the default parameters.
+    pub fn builder() -> TgvParamsBuilder {
+        TgvParamsBuilder { params: TgvParams::default() }
+    }
+
+    /// Checks that all weights are positive and that the step sizes satisfy
+    /// `tau * sigma * L^2 <= 1`, which guarantees convergence. `L^2` depends on the
+    /// boundary mode, see [`Boundary::operator_norm_squared`].
+    pub fn validate(&self) -> Result<(), TgvError> {
+        let positive = [
+            ("lambda", self.lambda),
+            ("alpha0", self.alpha0),
+            ("alpha1", self.alpha1),
+            ("tau", self.tau),
+            ("sigma", self.sigma),
+        ];
+        let tolerance = self.tolerance.map(|tolerance| ("tolerance", tolerance));
+        let huber_epsilon = (self.regularization == Regularization::HuberTv).then_some(("huber_epsilon", self.huber_epsilon));
+        let count_scale = (self.data_term == DataTerm::Kl).then_some(("count_scale", self.count_scale));
+        for (name, value) in positive.into_iter().chain(tolerance).chain(huber_epsilon).chain(count_scale) {
+            if value.is_nan() || value <= 0. {
+                return Err(TgvError::NotPositive { name, value });
+            }
+        }
+        if self.n_iter == 0 {
+            return Err(TgvError::NotPositive { name: "n_iter", value: 0. });
+        }
+        if self.check_interval == 0 {
+            return Err(TgvError::NotPositive { name: "check_interval", value: 0. });
+        }
+        self.check_step_sizes(self.boundary.operator_norm_squared())
+    }
+
+    /// Same as [`validate`](TgvParams::validate), for deblurring. The blur adds up to
+    /// one to `L^2`, since the kernel weights sum to one.
+    pub fn validate_for_deblurring(&self) -> Result<(), TgvError> {
+        self.validate()?;
+        self.check_step_sizes(self.boundary.operator_norm_squared() + 1.)
+    }
+
+    // Weight of the data term, `count_scale` for KL, which is only the Poisson
+    // likelihood of the counts themselves
+    pub(crate) fn data_weight(&self) -> f32 {
+        if self.data_term == DataTerm::Kl { self.count_scale } else { 1. }
+    }
+
+    fn check_step_sizes(&self, norm_squared: f32) -> Result<(), TgvError> {
+        if self.tau * self.sigma * norm_squared > 1. {
+            return Err(TgvError::StepSizeTooLarge { tau: self.tau, sigma: self.sigma, norm_squared });
+        }
+        Ok(())
+    }
+}
+
+/// Builder for [`TgvParams`], validated on [`build`](TgvParamsBuilder::build).
+#[derive(Clone, Copy, Debug)]
+pub struct TgvParamsBuilder {
+    params: TgvParams,
+}
+
+impl TgvParamsBuilder {
+    pub fn lambda(mut self, lambda: f32) -> Self {
+        self.params.lambda = lambda;
+        self
+    }
+
+    pub fn alpha0(mut self, alpha0: f32) -> Self {
+        self.params.alpha0 = alpha0;
+        self
+    }
+
+    pub fn alpha1(mut self, alpha1: f32) -> Self {
+        self.params.alpha1 = alpha1;
+        self
+    }
+
+    pub fn tau(mut self, tau: f32) -> Self {
+        self.params.tau = tau;
+        self
+    }
+
+    pub fn sigma(mut self, sigma: f32) -> Self {
+        self.params.sigma = sigma;
+        self
+    }
+
+    pub fn n_iter(mut self, n_iter: usize) -> Self {
+        self.params.n_iter = n_iter;
+        self
+    }
+
+    pub fn boundary(mut self, boundary: Boundary) -> Self {
+        self.params.boundary = boundary;
+        self
+    }
+
+    /// Stops the solver early once both residuals are below `tolerance`.
+    pub fn tolerance(mut self, tolerance: f32) -> Self {
+        self.params.tolerance = Some(tolerance);
+        self
+    }
+
+    pub fn check_interval(mut self, check_interval: usize) -> Self {
+        self.params.check_interval = check_interval;
+        self
+    }
+
+    pub fn compute_gap(mut self, compute_gap: bool) -> Self {
+        self.params.compute_gap = compute_gap;
+        self
+    }
+
+    pub fn regularization(mut self, regularization: Regularization) -> Self {
+        self.params.regularization = regularization;
+        self
+    }
+
+    pub fn huber_epsilon(mut self, huber_epsilon: f32) -> Self {
+        self.params.huber_epsilon = huber_epsilon;
+        self
+    }
+
+    pub fn data_term(mut self, data_term: DataTerm) -> Self {
+        self.params.data_term = data_term;
+        self
+    }
+
+    pub fn count_scale(mut self, count_scale: f32) -> Self {
+        self.params.count_scale = count_scale;
+        self
+    }
+
+    pub fn build(self) -> Result<TgvParams, TgvError> {
+        self.params.validate()?;
+        Ok(self.params)
+    }
+}
@@ -0,0 +1,320 @@
+use ndarray::{s, Array, Array2, Array3, Array4, ArrayView3, ArrayViewMut2, ArrayViewMut4, Axis, Dimension, Zip};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::operators::{divergence_into, gradient_into, sym_divergence_into, sym_gradient_into, Boundary};
+use crate::params::TgvParams;
+
+/// Which regularizer the solver uses, see [`TgvParams::regularization`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Regularization {
+    /// First order total variation `alpha1 ||grad u||_1`, see [`Tv`]. Prone to
+    /// staircasing on smooth ramps.
+    Tv,
+    /// Total variation with the Huber norm, quadratic below
+    /// [`TgvParams::huber_epsilon`], see [`Tv::huber`].
+    HuberTv,
+    /// Second order total generalized variation, see [`Tgv`].
+    #[default]
+    Tgv,
+}
+
+impl Regularization {
+    pub const ALL: [Regularization; 3] = [Regularization::Tv, Regularization::HuberTv, Regularization::Tgv];
+
+    /// Human readable name, e.g. for a UI.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Regularization::Tv => "TV",
+            Regularization::HuberTv => "Huber TV",
+            Regularization::Tgv => "TGV",
+        }
+    }
+
+    /// Allocates the regularizer for an image of shape `(h, w, channels)`.
+    pub fn build(self, dim: (usize, usize, usize), params: &TgvParams) -> Box<dyn Regularizer> {
+        match self {
+            Regularization::Tv => Box::new(Tv::new(dim, params)),
+            Regularization::HuberTv => Box::new(Tv::huber(dim, params, params.huber_epsilon)),
+            Regularization::Tgv => Box::new(Tgv::new(dim, params)),
+        }
+    }
+}
+
+/// Regularizer `R(u)` of the primal-dual solver, which minimizes `D(u, u0) + R(u)`
+/// for a [`DataTerm`](crate::DataTerm) `D`.
+///
+/// The regularizer is written as `R(u) = min_v max_y <K(u, v), y> - R*(y)` with its
+/// own dual variables `y`, and possibly auxiliary primal variables `v` (the vector
+/// field `w` of TGV). It owns both, and the solver only ever sees `u`. The operator `K`
+/// must be built from the finite differences of [`crate::operators`], so that the step
+/// size condition of [`TgvParams::validate`] covers it.
+///
+/// The dual variables are stored per channel, the pointwise norms couple the channels.
+pub trait Regularizer {
+    /// Dual ascent `y += sigma K(u_bar, v_bar)` followed by the proximal step of
+    /// `R*`, given the over-relaxed image `u_bar` of shape `(h, w, channels)`.
+    fn dual_step(&mut self, u_bar: &ArrayView3<f32>, sigma: f32);
+
+    /// Writes `-K_u^* y` for channel `c` into `out`, the direction in which the
+    /// regularizer pulls `u`. This is `div p` for total variation.
+    fn divergence_into(&self, c: usize, out: &mut ArrayViewMut2<f32>);
+
+    /// Primal descent and over-relaxation of the auxiliary primal variables, if any.
+    /// Called after [`dual_step`](Regularizer::dual_step).
+    fn primal_step(&mut self, tau: f32);
+
+    /// Remembers the dual variables, for [`changes`](Regularizer::changes) after the
+    /// next step.
+    fn save_dual(&mut self);
+
+    /// Sums of the squared changes of the auxiliary primal and of the dual variables
+    /// over the last step, each with its number of entries. The dual change is
+    /// relative to the last [`save_dual`](Regularizer::save_dual).
+    fn changes(&self) -> [(f64, usize); 2];
+
+    /// The value of `R(u)`.
+    fn energy(&mut self, u: &ArrayView3<f32>) -> f64;
+
+    /// The value of `R*(y)` at the current dual variables, leaving out the
+    /// constraints that the dual step enforces exactly.
+    fn dual_energy(&self) -> f64 {
+        0.
+    }
+}
+
+// Projects every pixel of `p` (shape (h, w, channels, components)) onto the ball of
+// radius `radius`, in place. The norm is taken jointly over the channels, which
+// couples the color channels. Used for both p (radius alpha1) and q (radius alpha0).
+fn project(p: &mut ArrayViewMut4<f32>, radius: f32) {
+    for mut row in p.outer_iter_mut() {
+        for mut pixel in row.outer_iter_mut() {
+            let norm = pixel.iter().map(|x| x.powi(2)).sum::<f32>().sqrt();
+            if norm > radius {
+                pixel *= radius / norm;
+            }
+        }
+    }
+}
+
+// Sum of squared differences of two arrays of the same shape
+pub(crate) fn squared_distance<D: Dimension>(a: &Array<f32, D>, b: &Array<f32, D>) -> f64 {
+    Zip::from(a).and(b).fold(0., |sum, &a, &b| sum + ((a - b) as f64).powi(2))
+}
+
+// Sum of the pointwise norms, given their squares
+fn total_norm(norms_squared: &Array2<f32>) -> f64 {
+    norms_squared.iter().map(|n| n.sqrt() as f64).sum()
+}
+
+// Adds the squared norm of every pixel of the vector fields in `fields` (shape
+// (h, w, components)) to `norms`
+fn add_squared_norms(norms: &mut Array2<f32>, fields: &ArrayView3<f32>) {
+    Zip::from(norms)
+        .and(fields.lanes(Axis(2)))
+        .for_each(|norm, field| *norm += field.dot(&field));
+}
+
+/// Total variation `lambda alpha1 ||grad u||_1`, or with a Huber norm
+/// `lambda alpha1 sum h(|grad u|)`, where `h(t) = t^2 / (2 epsilon)` below `epsilon`
+/// and `t - epsilon / 2` above. The Huber norm keeps smooth gradients smooth instead
+/// of flattening them into steps.
+///
+/// Uses `alpha1` as its weight and ignores `alpha0`.
+pub struct Tv {
+    weight: f32,
+    epsilon: f32,
+    boundary: Boundary,
+    p: Array4<f32>,
+    p_prev: Array4<f32>,
+    grad: Array3<f32>,
+    norm: Array2<f32>,
+}
+
+impl Tv {
+    /// Plain total variation for an image of shape `(h, w, channels)`.
+    pub fn new(dim: (usize, usize, usize), params: &TgvParams) -> Self {
+        Tv::huber(dim, params, 0.)
+    }
+
+    /// Huber total variation with threshold `epsilon`, on the scale of the image
+    /// values. An `epsilon` of zero gives plain total variation.
+    pub fn huber(dim: (usize, usize, usize), params: &TgvParams, epsilon: f32) -> Self {
+        let (height, width, channels) = dim;
+        Tv {
+            weight: params.lambda * params.alpha1,
+            epsilon,
+            boundary: params.boundary,
+            p: Array4::zeros((height, width, channels, 2)),
+            p_prev: Array4::zeros((height, width, channels, 2)),
+            grad: Array3::zeros((height, width, 2)),
+            norm: Array2::zeros((height, width)),
+        }
+    }
+}
+
+impl Regularizer for Tv {
+    fn dual_step(&mut self, u_bar: &ArrayView3<f32>, sigma: f32) {
+        for c in 0..u_bar.shape()[2] {
+            gradient_into(&u_bar.slice(s![.., .., c]), &mut self.grad.view_mut(), self.boundary);
+            Zip::from(self.p.slice_mut(s![.., .., c, ..]))
+                .and(&self.grad)
+                .for_each(|p, &grad| *p += sigma * grad);
+        }
+        // The Huber norm adds epsilon / (2 weight) |p|^2 to the conjugate, whose
+        // proximal step shrinks p before the projection
+        if self.epsilon > 0. {
+            self.p /= 1. + sigma * self.epsilon / self.weight;
+        }
+        project(&mut self.p.view_mut(), self.weight);
+    }
+
+    fn divergence_into(&self, c: usize, out: &mut ArrayViewMut2<f32>) {
+        divergence_into(&self.p.slice(s![.., .., c, ..]), out, self.boundary);
+    }
+
+    fn primal_step(&mut self, _tau: f32) {}
+
+    fn save_dual(&mut self) {
+        self.p_prev.assign(&self.p);
+    }
+
+    fn changes(&self) -> [(f64, usize); 2] {
+        [(0., 0), (squared_distance(&self.p, &self.p_prev), self.p.len())]
+    }
+
+    fn energy(&mut self, u: &ArrayView3<f32>) -> f64 {
+        self.norm.fill(0.);
+        for c in 0..u.shape()[2] {
+            gradient_into(&u.slice(s![.., .., c]), &mut self.grad.view_mut(), self.boundary);
+            add_squared_norms(&mut self.norm, &self.grad.view());
+        }
+        let epsilon = self.epsilon as f64;
+        let huber = |norm: f64| if norm < epsilon { norm.powi(2) / (2. * epsilon) } else { norm - epsilon / 2. };
+        self.weight as f64 * self.norm.iter().map(|n| huber(n.sqrt() as f64)).sum::<f64>()
+    }
+
+    fn dual_energy(&self) -> f64 {
+        let p_squared = self.p.iter().map(|&p| (p as f64).powi(2)).sum::<f64>();
+        self.epsilon as f64 / (2. * self.weight as f64) * p_squared
+    }
+}
+
+/// Second order total generalized variation
+/// `lambda min_w (alpha1 ||grad u - w||_1 + alpha0 ||sym_grad w||_1)`.
+pub struct Tgv {
+    alpha0: f32,
+    alpha1: f32,
+    boundary: Boundary,
+    w: Array4<f32>,
+    w_bar: Array4<f32>,
+    p: Array4<f32>,
+    q: Array4<f32>,
+    // Per-channel outputs of the differential operators
+    grad: Array3<f32>,
+    sym_grad: Array3<f32>,
+    sym_div: Array3<f32>,
+    // Dual iterates before the current step, for the dual residual
+    p_prev: Array4<f32>,
+    q_prev: Array4<f32>,
+    // Pointwise squared norms of grad u - w and sym_grad w, for the energy
+    norm1: Array2<f32>,
+    norm0: Array2<f32>,
+}
+
+impl Tgv {
+    /// Allocates the iterates for an image of shape `(h, w, channels)`.
+    pub fn new(dim: (usize, usize, usize), params: &TgvParams) -> Self {
+        let (height, width, channels) = dim;
+        Tgv {
+            alpha0: params.lambda * params.alpha0,
+            alpha1: params.lambda * params.alpha1,
+            boundary: params.boundary,
+            w: Array4::zeros((height, width, channels, 2)),
+            w_bar: Array4::zeros((height, width, channels, 2)),
+            p: Array4::zeros((height, width, channels, 2)),
+            q: Array4::zeros((height, width, channels, 3)),
+            grad: Array3::zeros((height, width, 2)),
+            sym_grad: Array3::zeros((height, width, 3)),
+            sym_div: Array3::zeros((height, width, 2)),
+            p_prev: Array4::zeros((height, width, channels, 2)),
+            q_prev: Array4::zeros((height, width, channels, 3)),
+            norm1: Array2::zeros((height, width)),
+            norm0: Array2::zeros((height, width)),
+        }
+    }
+}
+
+impl Regularizer for Tgv {
+    // p += sigma (grad u_bar - w_bar), q += sigma sym_grad w_bar. The differential
+    // operators act on each channel separately, only the projections couple them
+    fn dual_step(&mut self, u_bar: &ArrayView3<f32>, sigma: f32) {
+        for c in 0..u_bar.shape()[2] {
+            let w_bar_c = self.w_bar.slice(s![.., .., c, ..]);
+
+            gradient_into(&u_bar.slice(s![.., .., c]), &mut self.grad.view_mut(), self.boundary);
+            Zip::from(self.p.slice_mut(s![.., .., c, ..]))
+                .and(&self.grad)
+                .and(&w_bar_c)
+                .for_each(|p, &grad, &w_bar| *p += sigma * (grad - w_bar));
+
+            sym_gradient_into(&w_bar_c, &mut self.sym_grad.view_mut(), self.boundary);
+            Zip::from(self.q.slice_mut(s![.., .., c, ..]))
+                .and(&self.sym_grad)
+                .for_each(|q, &sym_grad| *q += sigma * sym_grad);
+        }
+        project(&mut self.p.view_mut(), self.alpha1);
+        project(&mut self.q.view_mut(), self.alpha0);
+    }
+
+    fn divergence_into(&self, c: usize, out: &mut ArrayViewMut2<f32>) {
+        divergence_into(&self.p.slice(s![.., .., c, ..]), out, self.boundary);
+    }
+
+    // w += tau (p + sym_div q), then w_bar = 2 w_new - w_old
+    fn primal_step(&mut self, tau: f32) {
+        for c in 0..self.w.shape()[2] {
+            sym_divergence_into(&self.q.slice(s![.., .., c, ..]), &mut self.sym_div.view_mut(), self.boundary);
+            Zip::from(self.w.slice_mut(s![.., .., c, ..]))
+                .and(self.w_bar.slice_mut(s![.., .., c, ..]))
+                .and(self.p.slice(s![.., .., c, ..]))
+                .and(&self.sym_div)
+                .for_each(|w, w_bar, &p, &sym_div| {
+                    let w_old = *w;
+                    *w = w_old + tau * (p + sym_div);
+                    *w_bar = 2. * *w - w_old;
+                });
+        }
+    }
+
+    fn save_dual(&mut self) {
+        self.p_prev.assign(&self.p);
+        self.q_prev.assign(&self.q);
+    }
+
+    // The over-relaxed w_bar - w is exactly the change w_new - w_old
+    fn changes(&self) -> [(f64, usize); 2] {
+        [
+            (squared_distance(&self.w_bar, &self.w), self.w.len()),
+            (squared_distance(&self.p, &self.p_prev) + squared_distance(&self.q, &self.q_prev), self.p.len() + self.q.len()),
+        ]
+    }
+
+    fn energy(&mut self, u: &ArrayView3<f32>) -> f64 {
+        // The pointwise norms are coupled across channels, like in the projections
+        self.norm1.fill(0.);
+        self.norm0.fill(0.);
+        for c in 0..u.shape()[2] {
+            let w_c = self.w.slice(s![.., .., c, ..]);
+
+            gradient_into(&u.slice(s![.., .., c]), &mut self.grad.view_mut(), self.boundary);
+            self.grad -= &w_c;
+            add_squared_norms(&mut self.norm1, &self.grad.view());
+
+            sym_gradient_into(&w_c, &mut self.sym_grad.view_mut(), self.boundary);
+            add_squared_norms(&mut self.norm0, &self.sym_grad.view());
+        }
+        self.alpha1 as f64 * total_norm(&self.norm1) + self.alpha0 as f64 * total_norm(&self.norm0)
+    }
+}
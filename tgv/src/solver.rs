@@ -0,0 +1,366 @@
+use std::ops::ControlFlow;
+
+use ndarray::{s, Array2, Array3, ArrayView2, ArrayView3, Axis, ShapeBuilder, Zip};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::blur::{Convolution, Kernel};
+use crate::data_term::DataTerm;
+use crate::params::TgvParams;
+use crate::regularizer::{squared_distance, Regularizer};
+
+/// Convergence measures of the solver after one iteration.
+///
+/// `primal` and `dual` are the root mean square changes of the primal variables
+/// (`u` and e.g. `w` of TGV) and of the dual variables (e.g. `p` and `q`) over that
+/// iteration, in the units of the image. Both go to zero as the iteration converges.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Residuals {
+    pub iteration: usize,
+    pub primal: f32,
+    pub dual: f32,
+    /// Primal-dual gap of the energy per pixel, if [`TgvParams::compute_gap`] is set.
+    /// For TGV the dual energy ignores the constraint `p = -sym_div(q)`, and for the
+    /// L1 data term the constraint `|div p| <= 1`. The iterates only satisfy them in
+    /// the limit, so this is an estimate until then. With the KL data term the gap is
+    /// infinite while `div p >= 1` at a pixel with nonzero counts. When deblurring,
+    /// the dual energy also ignores the constraint `blur^T r = div p` on the dual
+    /// variable `r` of the data term. When inpainting, it ignores the constraint
+    /// `div p = 0` at the missing pixels.
+    pub gap: Option<f32>,
+}
+
+/// Summary of a solver run.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TgvReport {
+    /// Number of iterations actually run.
+    pub iterations: usize,
+    /// Whether the run stopped early because the residuals met [`TgvParams::tolerance`].
+    pub converged: bool,
+    /// Whether the run was stopped by the progress callback.
+    pub cancelled: bool,
+    /// Residuals measured every [`TgvParams::check_interval`] iterations, and after
+    /// the last one.
+    pub history: Vec<Residuals>,
+}
+
+/// How `u0` was degraded besides the noise of the data term, see [`tgv_restore`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Degradation<'a> {
+    /// Blur to remove, see [`tgv_deblur`].
+    pub blur: Option<&'a Kernel>,
+    /// Pixels to fill in, see [`tgv_inpaint`]. Must have the height and width of `u0`.
+    pub missing: Option<ArrayView2<'a, bool>>,
+}
+
+// Mask for images without missing pixels
+static NONE_MISSING: bool = false;
+
+// Primal-dual iterates and scratch buffers, allocated once for the whole run
+struct Solver<'a, 'r> {
+    u0: ArrayView3<'a, f32>,
+    params: TgvParams,
+    u: Array3<f32>,
+    u_bar: Array3<f32>,
+    regularizer: &'r mut dyn Regularizer,
+    // Per-channel pull of the regularizer on u
+    div: Array2<f32>,
+    deblur: Option<Deblur>,
+    // Pixels without data, where only the regularizer acts. Broadcast from a single
+    // false when nothing is missing
+    missing: ArrayView2<'a, bool>,
+}
+
+// Blurring forward operator of the data term. The data term is then no longer
+// simple enough for a proximal step in u, so it gets a dual variable r instead
+struct Deblur {
+    convolution: Convolution,
+    r: Array3<f32>,
+    r_prev: Array3<f32>,
+    // Per-channel output of the convolution
+    blurred: Array2<f32>,
+}
+
+// Starting point of the iteration: `u0` with the missing pixels set to the mean of
+// the others in their channel, which is much closer than arbitrary values
+fn fill_missing(u0: &ArrayView3<f32>, missing: &ArrayView2<bool>) -> Array3<f32> {
+    let mut u = u0.to_owned();
+    let known = missing.iter().filter(|missing| !**missing).count();
+    if known == 0 || known == missing.len() {
+        return u;
+    }
+    for mut channel in u.axis_iter_mut(Axis(2)) {
+        let sum: f32 = Zip::from(&channel).and(missing).fold(0., |sum, &u, &missing| if missing { sum } else { sum + u });
+        let mean = sum / known as f32;
+        Zip::from(&mut channel).and(missing).for_each(|u, &missing| if missing { *u = mean });
+    }
+    u
+}
+
+impl<'a, 'r> Solver<'a, 'r> {
+    fn new(u0: &'a ArrayView3<f32>, params: &TgvParams, regularizer: &'r mut dyn Regularizer, degradation: &'a Degradation) -> Self {
+        let (height, width, _) = u0.dim();
+        let missing = match &degradation.missing {
+            Some(missing) => {
+                assert_eq!(missing.dim(), (height, width), "the missing pixel mask must have the height and width of the image");
+                missing.view()
+            }
+            None => ArrayView2::from_shape((height, width).strides((0, 0)), std::slice::from_ref(&NONE_MISSING)).unwrap(),
+        };
+        let u = fill_missing(u0, &missing);
+        Solver {
+            u0: u0.view(),
+            params: *params,
+            u_bar: u.clone(),
+            u,
+            regularizer,
+            div: Array2::zeros((height, width)),
+            deblur: degradation.blur.map(|kernel| Deblur {
+                convolution: Convolution::new(kernel, (height, width)),
+                r: Array3::zeros(u0.dim()),
+                r_prev: Array3::zeros(u0.dim()),
+                blurred: Array2::zeros((height, width)),
+            }),
+            missing,
+        }
+    }
+
+    // One primal-dual iteration, updating all state in place
+    fn step(&mut self) {
+        let TgvParams { tau, sigma, data_term, .. } = self.params;
+        let weight = self.params.data_weight();
+
+        let channels = self.u.shape()[2];
+
+        // Dual ascent, r += sigma blur(u_bar) followed by the proximal step of D*. The
+        // data term is zero at missing pixels, and its conjugate forces r = 0. The
+        // conjugate of the weighted w D is w D*(r / w)
+        self.regularizer.dual_step(&self.u_bar.view(), sigma);
+        if let Some(deblur) = &mut self.deblur {
+            for c in 0..channels {
+                deblur.convolution.apply(&self.u_bar.slice(s![.., .., c]), &mut deblur.blurred.view_mut(), false);
+                Zip::from(deblur.r.slice_mut(s![.., .., c]))
+                    .and(&deblur.blurred)
+                    .and(self.u0.slice(s![.., .., c]))
+                    .and(&self.missing)
+                    .for_each(|r, &blurred, &u0, &missing| {
+                        *r = if missing { 0. } else { weight * data_term.dual_prox((*r + sigma * blurred) / weight, u0, sigma / weight) };
+                    });
+            }
+        }
+
+        // Primal descent and over-relaxation: x_bar = 2 x_new - x_old
+        for c in 0..channels {
+            self.regularizer.divergence_into(c, &mut self.div.view_mut());
+            let u = self.u.slice_mut(s![.., .., c]);
+            let u_bar = self.u_bar.slice_mut(s![.., .., c]);
+            match &mut self.deblur {
+                // A zero step of the data term leaves missing pixels to the regularizer,
+                // it only keeps KL intensities non-negative
+                None => Zip::from(u).and(u_bar).and(self.u0.slice(s![.., .., c])).and(&self.div).and(&self.missing).for_each(
+                    |u, u_bar, &u0, &div, &missing| {
+                        let u_old = *u;
+                        *u = data_term.prox(u_old + tau * div, u0, if missing { 0. } else { weight * tau });
+                        *u_bar = 2. * *u - u_old;
+                    },
+                ),
+                Some(deblur) => {
+                    // The adjoint of the blur pulls u towards the data
+                    deblur.convolution.apply(&deblur.r.slice(s![.., .., c]), &mut deblur.blurred.view_mut(), true);
+                    Zip::from(u).and(u_bar).and(&self.div).and(&deblur.blurred).for_each(|u, u_bar, &div, &adjoint| {
+                        let u_old = *u;
+                        *u = u_old + tau * (div - adjoint);
+                        // Poisson intensities cannot be negative
+                        if data_term == DataTerm::Kl {
+                            *u = u.max(0.);
+                        }
+                        *u_bar = 2. * *u - u_old;
+                    });
+                }
+            }
+        }
+        self.regularizer.primal_step(tau);
+    }
+
+    // Must be called before a step whose residuals are measured
+    fn save_dual(&mut self) {
+        self.regularizer.save_dual();
+        if let Some(deblur) = &mut self.deblur {
+            deblur.r_prev.assign(&deblur.r);
+        }
+    }
+
+    // Residuals of the last step. The over-relaxed u_bar - u is exactly the change
+    // u_new - u_old
+    fn residuals(&mut self, iteration: usize) -> Residuals {
+        let [(primal, primal_len), (mut dual, mut dual_len)] = self.regularizer.changes();
+        if let Some(deblur) = &self.deblur {
+            dual += squared_distance(&deblur.r, &deblur.r_prev);
+            dual_len += deblur.r.len();
+        }
+        let primal = (squared_distance(&self.u_bar, &self.u) + primal) / (self.u.len() + primal_len) as f64;
+        let dual = dual / dual_len.max(1) as f64;
+        Residuals {
+            iteration,
+            primal: primal.sqrt() as f32,
+            dual: dual.sqrt() as f32,
+            gap: self.params.compute_gap.then(|| self.gap()),
+        }
+    }
+
+    // Primal energy D(u, u0) + R(u) minus the dual energy -D*(div p) - R*(p), per
+    // pixel. For the L2 data term D*(div p) = <u0, div p> + 1/2 ||div p||^2.
+    // When deblurring, the primal energy is D(blur(u), u0) + R(u) and the dual energy
+    // -D*(r) - R*(p). Missing pixels add to neither data energy
+    fn gap(&mut self) -> f32 {
+        let data_term = self.params.data_term;
+        let weight = self.params.data_weight() as f64;
+        let (height, width, channels) = self.u.dim();
+        let mut data = 0f64;
+        let mut dual = 0f64;
+        for c in 0..channels {
+            let u_c = self.u.slice(s![.., .., c]);
+            let u0_c = self.u0.slice(s![.., .., c]);
+            match &mut self.deblur {
+                None => {
+                    self.regularizer.divergence_into(c, &mut self.div.view_mut());
+                    Zip::from(u_c).and(u0_c).and(&self.div).and(&self.missing).for_each(|&u, &u0, &div, &missing| {
+                        if missing {
+                            return;
+                        }
+                        let (u, u0, div) = (u as f64, u0 as f64, div as f64);
+                        data += weight * data_term.energy(u, u0);
+                        dual -= weight * data_term.conjugate(div / weight, u0);
+                    });
+                }
+                Some(deblur) => {
+                    deblur.convolution.apply(&u_c, &mut deblur.blurred.view_mut(), false);
+                    Zip::from(&deblur.blurred).and(u0_c).and(deblur.r.slice(s![.., .., c])).and(&self.missing).for_each(|&blurred, &u0, &r, &missing| {
+                        if missing {
+                            return;
+                        }
+                        let (blurred, u0, r) = (blurred as f64, u0 as f64, r as f64);
+                        data += weight * data_term.energy(blurred, u0);
+                        dual -= weight * data_term.conjugate(r / weight, u0);
+                    });
+                }
+            }
+        }
+        dual -= self.regularizer.dual_energy();
+        let regularizer = self.regularizer.energy(&self.u.view());
+        ((data + regularizer - dual) / (height * width) as f64) as f32
+    }
+}
+
+/// Denoises a grayscale image with TGV.
+pub fn tgv_denoise(u0: &ArrayView2<f32>, params: &TgvParams) -> Array2<f32> {
+    tgv_denoise_with_progress(u0, params, |_| ControlFlow::Continue(()))
+}
+
+/// Same as [`tgv_denoise`], but calls `on_progress` with the number of completed
+/// iterations after every iteration.
+///
+/// Returning [`ControlFlow::Break`] from `on_progress` cancels the run, the current
+/// iterate is returned.
+pub fn tgv_denoise_with_progress(u0: &ArrayView2<f32>, params: &TgvParams, on_progress: impl FnMut(usize) -> ControlFlow<()>) -> Array2<f32> {
+    let u0 = u0.insert_axis(Axis(2));
+    let u = vtgv_denoise(&u0, params, on_progress);
+    u.remove_axis(Axis(2))
+}
+
+/// Vectorial TGV: denoises all channels of `u0` (shape `(h, w, channels)`) at once.
+///
+/// The pointwise norms of the regularizer are coupled across channels, so that edges
+/// stay aligned between channels. With a single channel this is plain scalar TGV.
+/// `on_progress` is called with the number of completed iterations, and cancels the
+/// run by returning [`ControlFlow::Break`].
+///
+/// All buffers are allocated once up front; the iterations themselves do not allocate.
+pub fn vtgv_denoise(u0: &ArrayView3<f32>, params: &TgvParams, on_progress: impl FnMut(usize) -> ControlFlow<()>) -> Array3<f32> {
+    vtgv_denoise_with_report(u0, params, on_progress).0
+}
+
+/// Same as [`vtgv_denoise`], but also returns the residual history of the run.
+///
+/// Stops before `n_iter` iterations if [`TgvParams::tolerance`] is met.
+pub fn vtgv_denoise_with_report(u0: &ArrayView3<f32>, params: &TgvParams, mut on_progress: impl FnMut(usize) -> ControlFlow<()>) -> (Array3<f32>, TgvReport) {
+    solve(u0, params, &Degradation::default(), |iteration, _| on_progress(iteration))
+}
+
+/// Denoises all channels of `u0` (shape `(h, w, channels)`) with any
+/// [`Regularizer`], ignoring [`TgvParams::regularization`]. Otherwise the same as
+/// [`vtgv_denoise_with_report`].
+///
+/// `regularizer` must have been created for the shape of `u0`, e.g. with
+/// [`Tv::new`](crate::Tv::new).
+pub fn denoise_with_regularizer(u0: &ArrayView3<f32>, params: &TgvParams, regularizer: &mut dyn Regularizer, mut on_progress: impl FnMut(usize) -> ControlFlow<()>) -> (Array3<f32>, TgvReport) {
+    solve_with(u0, params, regularizer, &Degradation::default(), |iteration, _| on_progress(iteration))
+}
+
+/// Deblurs and denoises all channels of `u0` (shape `(h, w, channels)`), i.e.
+/// minimizes `D(blur(u), u0) + R(u)` where `blur` convolves every channel with
+/// `kernel`. Otherwise the same as [`vtgv_denoise_with_report`].
+///
+/// The parameters should be checked with [`TgvParams::validate_for_deblurring`],
+/// since the blur tightens the step size condition.
+pub fn tgv_deblur(u0: &ArrayView3<f32>, kernel: &Kernel, params: &TgvParams, on_progress: impl FnMut(usize) -> ControlFlow<()>) -> (Array3<f32>, TgvReport) {
+    tgv_restore(u0, &Degradation { blur: Some(kernel), ..Degradation::default() }, params, on_progress)
+}
+
+/// Inpaints all channels of `u0` (shape `(h, w, channels)`): the pixels where
+/// `missing` (shape `(h, w)`) is true are filled in from their surroundings by the
+/// regularizer alone, the others are denoised as usual. The data term is dropped
+/// at the missing pixels, so their values in `u0` do not matter. Otherwise the
+/// same as [`vtgv_denoise_with_report`].
+///
+/// With TGV, the filled in regions continue the piecewise affine structure around
+/// them. They start out at the mean of the known pixels, and large holes take more
+/// iterations to fill than denoising needs.
+pub fn tgv_inpaint(u0: &ArrayView3<f32>, missing: &ArrayView2<bool>, params: &TgvParams, on_progress: impl FnMut(usize) -> ControlFlow<()>) -> (Array3<f32>, TgvReport) {
+    tgv_restore(u0, &Degradation { missing: Some(*missing), ..Degradation::default() }, params, on_progress)
+}
+
+/// Deblurs and inpaints all channels of `u0` at once, combining [`tgv_deblur`] and
+/// [`tgv_inpaint`]. The missing pixels are those of the blurred image `u0`.
+///
+/// With a blur, check the parameters with [`TgvParams::validate_for_deblurring`].
+pub fn tgv_restore(u0: &ArrayView3<f32>, degradation: &Degradation, params: &TgvParams, mut on_progress: impl FnMut(usize) -> ControlFlow<()>) -> (Array3<f32>, TgvReport) {
+    solve(u0, params, degradation, |iteration, _| on_progress(iteration))
+}
+
+// Runs the solver with the regularizer of `params` on the `degradation` of `u0`,
+// and passes the current iterate to `on_iteration` after every iteration
+pub(crate) fn solve(u0: &ArrayView3<f32>, params: &TgvParams, degradation: &Degradation, on_iteration: impl FnMut(usize, &ArrayView3<f32>) -> ControlFlow<()>) -> (Array3<f32>, TgvReport) {
+    let mut regularizer = params.regularization.build(u0.dim(), params);
+    solve_with(u0, params, regularizer.as_mut(), degradation, on_iteration)
+}
+
+fn solve_with(u0: &ArrayView3<f32>, params: &TgvParams, regularizer: &mut dyn Regularizer, degradation: &Degradation, mut on_iteration: impl FnMut(usize, &ArrayView3<f32>) -> ControlFlow<()>) -> (Array3<f32>, TgvReport) {
+    let mut solver = Solver::new(u0, params, regularizer, degradation);
+    let mut report = TgvReport::default();
+    for i in 0..params.n_iter {
+        let iteration = i + 1;
+        let check = iteration % params.check_interval == 0 || iteration == params.n_iter;
+        if check {
+            solver.save_dual();
+        }
+        solver.step();
+        report.iterations = iteration;
+        if on_iteration(iteration, &solver.u.view()).is_break() {
+            report.cancelled = true;
+            break;
+        }
+
+        if check {
+            let residuals = solver.residuals(iteration);
+            report.history.push(residuals);
+            if params.tolerance.is_some_and(|tolerance| residuals.primal <= tolerance && residuals.dual <= tolerance) {
+                report.converged = true;
+                break;
+            }
+        }
+    }
+    (solver.u, report)
+}
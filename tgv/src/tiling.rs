@@ -0,0 +1,142 @@
+use std::ops::{ControlFlow, Range};
+
+use ndarray::{s, Array2, Array3, ArrayView2, ArrayView3, Axis, Zip};
+use rayon::prelude::*;
+
+use crate::color::{restore_color, ColorMode};
+use crate::params::{TgvError, TgvParams};
+use crate::solver::{tgv_denoise, Degradation};
+
+/// How the overlapping halos of neighbouring tiles are combined when stitching.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Blend {
+    /// Keep only the core of every tile, the halos only provide context to the solver.
+    Discard,
+    /// Blend the halos into the neighbouring tiles with a linear window.
+    Feather,
+}
+
+/// Tiling of [`parallel_tgv_denoise`] and [`parallel_tgv_denoise_color`].
+///
+/// The image is cut into cores of `tile_size` x `tile_size` pixels, with smaller cores
+/// along the right and bottom edges when the image size is not a multiple of
+/// `tile_size`. Every core is denoised together with a halo of `overlap` pixels on
+/// each side, so that the boundary conditions of the solver do not show up as seams
+/// between tiles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TileConfig {
+    pub tile_size: usize,
+    pub overlap: usize,
+    pub blend: Blend,
+}
+
+impl Default for TileConfig {
+    fn default() -> Self {
+        TileConfig { tile_size: 128, overlap: 16, blend: Blend::Feather }
+    }
+}
+
+struct Tile {
+    // Region written to the output with full weight
+    core: [Range<usize>; 2],
+    // Core plus halo, clipped to the image
+    extended: [Range<usize>; 2],
+}
+
+fn tiles(shape: [usize; 2], config: &TileConfig) -> Vec<Tile> {
+    let tile_size = config.tile_size;
+    // Round up, the last row and column of tiles may be partial
+    let num_tiles_y = shape[0].div_ceil(tile_size);
+    let num_tiles_x = shape[1].div_ceil(tile_size);
+
+    let mut tiles = Vec::new();
+    for i in 0..num_tiles_y {
+        for j in 0..num_tiles_x {
+            let core = [
+                (i * tile_size)..((i + 1) * tile_size).min(shape[0]),
+                (j * tile_size)..((j + 1) * tile_size).min(shape[1]),
+            ];
+            let extended = [0, 1].map(|axis| {
+                core[axis].start.saturating_sub(config.overlap)..(core[axis].end + config.overlap).min(shape[axis])
+            });
+            tiles.push(Tile { core, extended });
+        }
+    }
+    tiles
+}
+
+// 1D blending window over `extended`: 1 on the core, then decreasing linearly
+// to 1 / (overlap + 1) at the outer edge of the halo
+fn window(core: &Range<usize>, extended: &Range<usize>, overlap: usize) -> Vec<f32> {
+    extended.clone().map(|x| {
+        let distance = core.start.saturating_sub(x) + x.saturating_sub(core.end - 1);
+        1. - distance as f32 / (overlap + 1) as f32
+    }).collect()
+}
+
+/// Splits `u0` (shape `(h, w, channels)`) into overlapping tiles, runs `denoise` on
+/// every tile on the rayon thread pool, and stitches the results together.
+///
+/// `denoise` must return an array of the same shape as its input.
+pub fn tiled_denoise(u0: &ArrayView3<f32>, config: &TileConfig, denoise: impl Fn(&ArrayView3<f32>) -> Array3<f32> + Sync) -> Array3<f32> {
+    assert!(config.tile_size > 0, "tile_size must be positive");
+    let (height, width, channels) = u0.dim();
+    let tiles = tiles([height, width], config);
+
+    let denoised_tiles: Vec<Array3<f32>> = tiles.par_iter().map(|tile| {
+        let [rows, cols] = tile.extended.clone();
+        denoise(&u0.slice(s![rows, cols, ..]))
+    }).collect();
+
+    let mut accumulated = Array3::<f32>::zeros((height, width, channels));
+    let mut weights = Array2::<f32>::zeros((height, width));
+    for (tile, denoised) in tiles.iter().zip(&denoised_tiles) {
+        match config.blend {
+            Blend::Discard => {
+                let [rows, cols] = tile.core.clone();
+                let offset = [rows.start - tile.extended[0].start, cols.start - tile.extended[1].start];
+                let core = denoised.slice(s![offset[0]..offset[0] + rows.len(), offset[1]..offset[1] + cols.len(), ..]);
+                accumulated.slice_mut(s![rows.clone(), cols.clone(), ..]).assign(&core);
+                weights.slice_mut(s![rows, cols]).fill(1.);
+            }
+            Blend::Feather => {
+                let window_y = window(&tile.core[0], &tile.extended[0], config.overlap);
+                let window_x = window(&tile.core[1], &tile.extended[1], config.overlap);
+                let tile_weights = Array2::from_shape_fn((window_y.len(), window_x.len()), |(i, j)| window_y[i] * window_x[j]);
+
+                let [rows, cols] = tile.extended.clone();
+                Zip::from(accumulated.slice_mut(s![rows.clone(), cols.clone(), ..]).lanes_mut(Axis(2)))
+                    .and(denoised.lanes(Axis(2)))
+                    .and(&tile_weights)
+                    .for_each(|mut acc, value, &weight| acc.scaled_add(weight, &value));
+                weights.slice_mut(s![rows, cols]).zip_mut_with(&tile_weights, |w, &t| *w += t);
+            }
+        }
+    }
+
+    // The cores cover the whole image, so every weight is at least 1
+    Zip::from(accumulated.lanes_mut(Axis(2)))
+        .and(&weights)
+        .for_each(|mut acc, &weight| acc /= weight);
+    accumulated
+}
+
+/// Denoises a grayscale image by splitting it into overlapping tiles that are solved
+/// independently on the rayon thread pool.
+pub fn parallel_tgv_denoise(u0: &ArrayView2<f32>, params: &TgvParams, config: &TileConfig) -> Array2<f32> {
+    let u0 = u0.insert_axis(Axis(2));
+    let denoised = tiled_denoise(&u0, config, |tile| {
+        let tile = tile.index_axis(Axis(2), 0);
+        tgv_denoise(&tile, params).insert_axis(Axis(2))
+    });
+    denoised.remove_axis(Axis(2))
+}
+
+/// Tiled, multi-threaded version of [`tgv_denoise_color`](crate::tgv_denoise_color),
+/// failing in the same cases.
+pub fn parallel_tgv_denoise_color(u0: &ArrayView3<f32>, mode: ColorMode, params: &TgvParams, config: &TileConfig) -> Result<Array3<f32>, TgvError> {
+    mode.validate(params)?;
+    Ok(tiled_denoise(u0, config, |tile| {
+        restore_color(tile, &Degradation::default(), mode, params, |_| ControlFlow::Continue(())).0
+    }))
+}
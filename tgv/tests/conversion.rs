@@ -0,0 +1,178 @@
+use std::ops::ControlFlow;
+
+use image::{DynamicImage, ImageBuffer, Luma, Rgb, Rgba};
+use ndarray::{Array3, ArrayView3};
+use tgv::{array_to_image, image_to_array, vtgv_denoise_with_report, BitDepth, DataTerm, OutputMapping, TgvParams};
+
+// Deliberately not square, so that swapped axes cannot go unnoticed
+const WIDTH: u32 = 7;
+const HEIGHT: u32 = 4;
+
+fn rgb_value(x: u32, y: u32, c: u32) -> u32 {
+    (x * 31 + y * 7 + c * 90) % 256
+}
+
+fn rgb8(img: &Array3<f32>) -> Vec<u8> {
+    array_to_image(&img.view(), BitDepth::Eight).into_rgb8().into_raw()
+}
+
+#[test]
+fn eight_bit_output_is_rounded() {
+    let img = Array3::from_shape_vec((1, 2, 3), vec![0.4, 0.5, 1.49, 127.5, 127.6, 254.6]).unwrap();
+    assert_eq!(rgb8(&img), [0, 1, 1, 128, 128, 255]);
+}
+
+#[test]
+fn out_of_range_values_are_clamped() {
+    let img = Array3::from_shape_vec((1, 2, 3), vec![-20., -0.6, f32::NAN, 255.4, 256., 1e6]).unwrap();
+    assert_eq!(rgb8(&img), [0, 0, 0, 255, 255, 255]);
+
+    let rgb16 = array_to_image(&img.view(), BitDepth::Sixteen).into_rgb16().into_raw();
+    assert_eq!(rgb16, [0, 0, 0, 65535, 65535, 65535]);
+}
+
+#[test]
+fn sixteen_bit_output_uses_the_full_range() {
+    let img = Array3::from_shape_vec((1, 1, 3), vec![0., 127.5, 255.]).unwrap();
+    let rgb16 = array_to_image(&img.view(), BitDepth::Sixteen).into_rgb16().into_raw();
+    assert_eq!(rgb16, [0, 32768, 65535]);
+}
+
+#[test]
+fn float_output_is_not_clamped() {
+    let img = Array3::from_shape_vec((1, 1, 3), vec![-25.5, 51., 510.]).unwrap();
+    let DynamicImage::ImageRgb32F(rgb) = array_to_image(&img.view(), BitDepth::Float) else {
+        panic!("expected a float image");
+    };
+    assert_eq!(rgb.into_raw(), [-0.1, 0.2, 2.]);
+}
+
+#[test]
+fn clamp_mapping_only_clamps() {
+    let img = Array3::from_shape_vec((1, 2, 3), vec![-3., 0., 12.25, 200., 255., 300.]).unwrap();
+    let mapped = OutputMapping::Clamp.apply(&img.view());
+    assert_eq!(mapped.into_raw_vec_and_offset().0, [0., 0., 12.25, 200., 255., 255.]);
+}
+
+#[test]
+fn percentile_mapping_stretches_to_the_full_range() {
+    // Gray pixels with values 50, 51, ..., 149
+    let values: Vec<f32> = (0..300).map(|i| 50. + (i / 3) as f32).collect();
+    let img = Array3::from_shape_vec((100, 1, 3), values).unwrap();
+    let mapped = OutputMapping::Percentile { low: 10., high: 90. }.apply(&img.view());
+
+    let at = |value: f32| mapped[[(value - 50.) as usize, 0, 0]];
+    // 10th and 90th percentile of 50..=149
+    assert_eq!(at(60.), 0.);
+    assert_eq!(at(139.), 255.);
+    assert!((at(100.) - 255. * 40. / 79.).abs() < 1e-3);
+    // Tails are clamped
+    assert_eq!(at(50.), 0.);
+    assert_eq!(at(149.), 255.);
+}
+
+#[test]
+fn percentile_mapping_leaves_flat_images_alone() {
+    let img = Array3::from_elem((4, 3, 3), 80f32);
+    let mapped = OutputMapping::Percentile { low: 1., high: 99. }.apply(&img.view());
+    assert_eq!(mapped, img);
+    let empty = ArrayView3::<f32>::from_shape((0, 0, 3), &[]).unwrap();
+    assert_eq!(OutputMapping::Percentile { low: 1., high: 99. }.apply(&empty).len(), 0);
+}
+
+#[test]
+fn eight_bit_rgb_round_trips() {
+    let img = ImageBuffer::from_fn(WIDTH, HEIGHT, |x, y| Rgb([0, 1, 2].map(|c| rgb_value(x, y, c) as u8)));
+    let (array, depth) = image_to_array(&DynamicImage::ImageRgb8(img.clone()));
+
+    assert_eq!(depth, BitDepth::Eight);
+    assert_eq!(array.dim(), (HEIGHT as usize, WIDTH as usize, 3));
+    for (x, y, pixel) in img.enumerate_pixels() {
+        for c in 0..3 {
+            assert_eq!(array[[y as usize, x as usize, c]], pixel[c] as f32);
+        }
+    }
+    assert_eq!(array_to_image(&array.view(), depth), DynamicImage::ImageRgb8(img));
+}
+
+#[test]
+fn sixteen_bit_rgb_round_trips() {
+    let img = ImageBuffer::from_fn(WIDTH, HEIGHT, |x, y| Rgb([0, 1, 2].map(|c| (rgb_value(x, y, c) * 256 + x) as u16)));
+    let (array, depth) = image_to_array(&DynamicImage::ImageRgb16(img.clone()));
+
+    assert_eq!(depth, BitDepth::Sixteen);
+    assert_eq!(array.dim(), (HEIGHT as usize, WIDTH as usize, 3));
+    // On the 0..255 scale, without quantizing to 8 bits
+    let expected = (31. * 256. + 1.) * 255. / 65535.;
+    assert!((array[[0, 1, 0]] - expected).abs() < 1e-4);
+    assert_eq!(array_to_image(&array.view(), depth), DynamicImage::ImageRgb16(img));
+}
+
+#[test]
+fn float_rgb_round_trips() {
+    let img = ImageBuffer::from_fn(WIDTH, HEIGHT, |x, y| Rgb([0, 1, 2].map(|c| rgb_value(x, y, c) as f32 / 300.)));
+    let (array, depth) = image_to_array(&DynamicImage::ImageRgb32F(img.clone()));
+
+    assert_eq!(depth, BitDepth::Float);
+    assert_eq!(array.dim(), (HEIGHT as usize, WIDTH as usize, 3));
+    let DynamicImage::ImageRgb32F(back) = array_to_image(&array.view(), depth) else {
+        panic!("expected a float image");
+    };
+    for (a, b) in img.iter().zip(back.iter()) {
+        assert!((a - b).abs() < 1e-6, "{} vs {}", a, b);
+    }
+}
+
+#[test]
+fn gray_images_fill_all_channels() {
+    let img = ImageBuffer::from_fn(WIDTH, HEIGHT, |x, y| Luma([(x * 9000 + y * 11) as u16]));
+    let (array, depth) = image_to_array(&DynamicImage::ImageLuma16(img.clone()));
+
+    assert_eq!(depth, BitDepth::Sixteen);
+    assert_eq!(array.dim(), (HEIGHT as usize, WIDTH as usize, 3));
+    for (x, y, pixel) in img.enumerate_pixels() {
+        let expected = pixel[0] as f32 * 255. / 65535.;
+        for c in 0..3 {
+            assert_eq!(array[[y as usize, x as usize, c]], expected);
+        }
+    }
+    assert_eq!(array_to_image(&array.view(), depth).into_luma16(), img);
+}
+
+#[test]
+fn alpha_is_dropped() {
+    let img = ImageBuffer::from_fn(WIDTH, HEIGHT, |x, y| Rgba([x as u8, y as u8, 9, 128]));
+    let (array, depth) = image_to_array(&DynamicImage::ImageRgba8(img));
+
+    assert_eq!(depth, BitDepth::Eight);
+    assert_eq!(array.dim(), (HEIGHT as usize, WIDTH as usize, 3));
+    assert_eq!(array.slice(ndarray::s![3, 6, ..]).to_vec(), [6., 3., 9.]);
+}
+
+#[test]
+fn kl_count_scale_recovers_sixteen_bit_counts() {
+    // Raw photon counts of dim blocks with deterministic scatter, stored at 16 bits
+    let counts = |x: u32, y: u32| (20 + 40 * ((x / 6 + y / 5) % 2) + (x * 7 + y * 13) % 9) as u16;
+    let (width, height) = (24, 20);
+    let img = DynamicImage::ImageLuma16(ImageBuffer::from_fn(width, height, |x, y| Luma([counts(x, y)])));
+    let (u0, depth) = image_to_array(&img);
+    let gray = u0.index_axis(ndarray::Axis(2), 0).to_owned().insert_axis(ndarray::Axis(2));
+    let raw = Array3::from_shape_fn((height as usize, width as usize, 1), |(y, x, _)| counts(x as u32, y as u32) as f32);
+
+    // The scaled image with its count scale is the same Poisson model as the raw
+    // counts, with lambda converted to the 0..255 scale. With the step sizes
+    // converted as well, the iterations are the same
+    let scale = depth.counts_per_unit();
+    let raw_params = TgvParams::builder().lambda(0.5).data_term(DataTerm::Kl).build().unwrap();
+    let params = TgvParams {
+        lambda: 0.5 * scale,
+        tau: raw_params.tau / (scale * scale),
+        sigma: raw_params.sigma * scale * scale,
+        count_scale: scale,
+        ..raw_params
+    };
+    let (scaled, _) = vtgv_denoise_with_report(&gray.view(), &params, |_| ControlFlow::Continue(()));
+    let (expected, _) = vtgv_denoise_with_report(&raw.view(), &raw_params, |_| ControlFlow::Continue(()));
+    let error = (&scaled * scale - &expected).mapv(f32::abs).mean().unwrap();
+    assert!(error < 1e-3 * expected.mean().unwrap(), "{} vs mean {}", error, expected.mean().unwrap());
+}
@@ -0,0 +1,94 @@
+use std::ops::ControlFlow;
+
+use ndarray::{s, Array2, Axis};
+use tgv::{tgv_deblur, DataTerm, Kernel, TgvError, TgvParams, MAX_KERNEL_SIZE};
+
+// Bright square on a dark background, with a size that is no power of two
+fn square(h: usize, w: usize) -> Array2<f32> {
+    Array2::from_shape_fn((h, w), |(i, j)| if (6..h - 6).contains(&i) && (5..w - 7).contains(&j) { 200. } else { 40. })
+}
+
+fn mean_error(a: &Array2<f32>, b: &Array2<f32>) -> f32 {
+    (a - b).mapv(f32::abs).mean().unwrap()
+}
+
+#[test]
+fn kernels_are_normalized() {
+    let gaussian = Kernel::gaussian(1.5).unwrap();
+    assert_eq!(gaussian.shape(), (11, 11));
+    assert!((gaussian.weights().sum() - 1.).abs() < 1e-5);
+
+    // Horizontal motion only spreads along the middle row
+    let motion = Kernel::motion(6., 0.).unwrap();
+    let (rows, _) = motion.shape();
+    assert!((motion.weights().row(rows / 2).sum() - 1.).abs() < 1e-5);
+
+    assert_eq!(Kernel::new(&Array2::from_elem((3, 3), -1.).view()), Err(TgvError::InvalidKernel("weights must be finite and non-negative")));
+    assert!(Kernel::new(&Array2::zeros((3, 3)).view()).is_err());
+    assert!(Kernel::gaussian(0.).is_err());
+}
+
+#[test]
+fn kernel_sizes_are_bounded() {
+    let not_finite = Err(TgvError::InvalidKernel("the blur size must be finite"));
+    assert_eq!(Kernel::gaussian(f32::INFINITY), not_finite);
+    assert_eq!(Kernel::motion(f32::INFINITY, 30.), not_finite);
+
+    let too_large = Err(TgvError::InvalidKernel("the blur is larger than the maximum kernel size"));
+    assert_eq!(Kernel::gaussian(1e6), too_large);
+    assert_eq!(Kernel::motion(MAX_KERNEL_SIZE as f32 + 1., 0.), too_large);
+    // The largest kernels are still allowed
+    assert_eq!(Kernel::gaussian((MAX_KERNEL_SIZE / 2) as f32 / 3.).unwrap().shape(), (MAX_KERNEL_SIZE, MAX_KERNEL_SIZE));
+    assert_eq!(Kernel::motion((MAX_KERNEL_SIZE - 1) as f32, 0.).unwrap().shape(), (MAX_KERNEL_SIZE, MAX_KERNEL_SIZE));
+}
+
+#[test]
+fn fft_convolution_matches_direct() {
+    let u = Array2::from_shape_fn((21, 17), |(i, j)| ((i * 7 + j * 13) % 23) as f32);
+    // A small kernel is applied directly, the same one padded with zeros by FFT
+    let small = Kernel::gaussian(1.).unwrap();
+    let mut padded = Array2::zeros((15, 15));
+    padded.slice_mut(s![4..11, 4..11]).assign(&small.weights());
+    let padded = Kernel::new(&padded.view()).unwrap();
+
+    let direct = small.convolve(&u.view());
+    let fft = padded.convolve(&u.view());
+    assert!(mean_error(&direct, &fft) < 1e-4);
+    // The blur keeps the mean brightness
+    assert!((direct.mean().unwrap() - u.mean().unwrap()).abs() < 1e-3);
+}
+
+#[test]
+fn deblurring_sharpens_edges() {
+    let clean = square(30, 26);
+    for kernel in [Kernel::gaussian(1.5).unwrap(), Kernel::motion(7., 30.).unwrap()] {
+        let blurred = kernel.convolve(&clean.view());
+        // Deconvolution moves u far from the data, a large primal step gets there sooner
+        let params = TgvParams::builder().lambda(0.2).tau(0.5).sigma(0.15).n_iter(1000).build().unwrap();
+        params.validate_for_deblurring().unwrap();
+        let (u, report) = tgv_deblur(&blurred.view().insert_axis(Axis(2)), &kernel, &params, |_| ControlFlow::Continue(()));
+        let u = u.remove_axis(Axis(2));
+
+        assert_eq!(report.iterations, 1000);
+        let (before, after) = (mean_error(&blurred, &clean), mean_error(&u, &clean));
+        assert!(after < 0.1 * before, "{:?}: {} vs {}", kernel.shape(), after, before);
+    }
+}
+
+#[test]
+fn deblurring_keeps_poisson_intensities_positive() {
+    let clean = square(20, 20).mapv(|x| x / 40.);
+    let kernel = Kernel::gaussian(1.).unwrap();
+    let blurred = kernel.convolve(&clean.view());
+    let params = TgvParams::builder().lambda(0.05).n_iter(300).data_term(DataTerm::Kl).build().unwrap();
+    let (u, _) = tgv_deblur(&blurred.view().insert_axis(Axis(2)), &kernel, &params, |_| ControlFlow::Continue(()));
+    assert!(u.iter().all(|&x| x >= 0.));
+    assert!(mean_error(&u.remove_axis(Axis(2)), &clean) < mean_error(&blurred, &clean));
+}
+
+#[test]
+fn blur_tightens_step_sizes() {
+    let params = TgvParams { tau: 0.28, sigma: 0.28, ..TgvParams::default() };
+    assert!(params.validate().is_ok());
+    assert!(matches!(params.validate_for_deblurring(), Err(TgvError::StepSizeTooLarge { .. })));
+}
@@ -0,0 +1,70 @@
+use std::ops::ControlFlow;
+
+use ndarray::{s, Array2, Array3, Axis};
+use tgv::{tgv_inpaint, tgv_restore_color_with_report, vtgv_denoise_with_report, ColorMode, Degradation, DataTerm, TgvParams};
+
+// Smooth ramp, which TGV reproduces without staircasing
+fn ramp(h: usize, w: usize) -> Array2<f32> {
+    Array2::from_shape_fn((h, w), |(i, j)| 50. + 3. * i as f32 + 2. * j as f32)
+}
+
+// A hole in the middle of the image
+fn hole(h: usize, w: usize) -> Array2<bool> {
+    Array2::from_shape_fn((h, w), |(i, j)| (10..18).contains(&i) && (12..20).contains(&j))
+}
+
+fn max_error_in_hole(u: &Array2<f32>, clean: &Array2<f32>) -> f32 {
+    (u - clean).slice(s![10..18, 12..20]).iter().fold(0f32, |max, e| max.max(e.abs()))
+}
+
+#[test]
+fn inpainting_continues_a_ramp() {
+    let clean = ramp(28, 32);
+    let missing = hole(28, 32);
+    // Scribble over the hole, only the mask should matter there
+    let damaged = Array2::from_shape_fn(clean.dim(), |(i, j)| if missing[[i, j]] { 255. } else { clean[[i, j]] });
+    let params = TgvParams::builder().lambda(1.).n_iter(400).build().unwrap();
+    let (u, report) = tgv_inpaint(&damaged.view().insert_axis(Axis(2)), &missing.view(), &params, |_| ControlFlow::Continue(()));
+    let u = u.remove_axis(Axis(2));
+
+    assert_eq!(report.iterations, 400);
+    assert!(max_error_in_hole(&u, &clean) < 2., "{}", max_error_in_hole(&u, &clean));
+    assert!(max_error_in_hole(&damaged, &clean) > 100.);
+}
+
+#[test]
+fn nothing_missing_is_plain_denoising() {
+    let noisy = Array3::from_shape_fn((12, 15, 2), |(i, j, c)| ((i * 7 + j * 13 + c * 5) % 11) as f32 * 10.);
+    for data_term in DataTerm::ALL {
+        let params = TgvParams::builder().n_iter(50).data_term(data_term).build().unwrap();
+        let missing = Array2::from_elem((12, 15), false);
+        let (inpainted, _) = tgv_inpaint(&noisy.view(), &missing.view(), &params, |_| ControlFlow::Continue(()));
+        let (denoised, _) = vtgv_denoise_with_report(&noisy.view(), &params, |_| ControlFlow::Continue(()));
+        assert_eq!(inpainted, denoised, "{:?}", data_term);
+    }
+}
+
+#[test]
+fn color_modes_fill_the_same_pixels() {
+    let clean = ramp(28, 32);
+    let missing = hole(28, 32);
+    let rgb = ndarray::stack![Axis(2), clean, clean.mapv(|x| x / 2.), clean.mapv(|x| 255. - x)];
+    let damaged = Array3::from_shape_fn(rgb.dim(), |(i, j, c)| if missing[[i, j]] { 0. } else { rgb[[i, j, c]] });
+    let params = TgvParams::builder().lambda(1.).n_iter(400).build().unwrap();
+    let degradation = Degradation { missing: Some(missing.view()), ..Degradation::default() };
+    for mode in [ColorMode::Vectorial, ColorMode::PerChannel, ColorMode::YCbCr] {
+        let (u, _) = tgv_restore_color_with_report(&damaged.view(), &degradation, mode, &params, |_| ControlFlow::Continue(())).unwrap();
+        for c in 0..3 {
+            let error = max_error_in_hole(&u.index_axis(Axis(2), c).to_owned(), &rgb.index_axis(Axis(2), c).to_owned());
+            assert!(error < 2., "{:?}, channel {}: {}", mode, c, error);
+        }
+    }
+}
+
+#[test]
+#[should_panic(expected = "missing pixel mask")]
+fn mask_must_match_the_image() {
+    let u0 = Array3::zeros((10, 10, 1));
+    let missing = Array2::from_elem((10, 9), false);
+    tgv_inpaint(&u0.view(), &missing.view(), &TgvParams::default(), |_| ControlFlow::Continue(()));
+}
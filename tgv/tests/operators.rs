@@ -0,0 +1,74 @@
+use ndarray::{s, Array2, Array3};
+use tgv::{divergence, gradient, sym_divergence, sym_gradient, Boundary, TgvParams};
+
+// Deterministic pseudo-random values, so the tests need no extra dependencies
+fn pseudo_random(n: usize, seed: usize) -> Vec<f32> {
+    (0..n).map(|i| (((i + seed) * 7919 % 1009) as f32 / 1009.0) - 0.5).collect()
+}
+
+#[test]
+fn gradient_of_constant_image_is_zero() {
+    let u = Array2::<f32>::from_elem((7, 9), 3.5);
+    for boundary in Boundary::ALL {
+        let grad = gradient(&u.view(), boundary);
+        assert_eq!(grad.dim(), (7, 9, 2));
+        assert!(grad.iter().all(|&x| x == 0.), "{:?}", boundary);
+    }
+}
+
+#[test]
+fn gradient_components_follow_columns_then_rows() {
+    // u increases by 1 along x (columns) and by 10 along y (rows)
+    let u = Array2::from_shape_fn((4, 5), |(i, j)| (10 * i + j) as f32);
+    let grad = gradient(&u.view(), Boundary::Neumann);
+    assert_eq!(grad[[1, 1, 0]], 1.);
+    assert_eq!(grad[[1, 1, 1]], 10.);
+}
+
+#[test]
+fn boundary_modes_differ_only_at_the_last_pixel() {
+    let u = Array2::from_shape_fn((3, 4), |(_, j)| (j * j) as f32);
+    let periodic = gradient(&u.view(), Boundary::Periodic);
+    let neumann = gradient(&u.view(), Boundary::Neumann);
+    let symmetric = gradient(&u.view(), Boundary::Symmetric);
+
+    assert_eq!(periodic.slice(s![.., ..3, 0]), neumann.slice(s![.., ..3, 0]));
+    assert_eq!(symmetric.slice(s![.., ..3, 0]), neumann.slice(s![.., ..3, 0]));
+    // Last column: wrap around to u = 0, zero flux, and mirrored u[2] - u[3]
+    assert_eq!(periodic[[0, 3, 0]], -9.);
+    assert_eq!(neumann[[0, 3, 0]], 0.);
+    assert_eq!(symmetric[[0, 3, 0]], -5.);
+}
+
+#[test]
+fn default_boundary_is_periodic() {
+    // The wrap around of the original solver, which existing callers rely on
+    assert_eq!(Boundary::default(), Boundary::Periodic);
+    assert_eq!(TgvParams::default().boundary, Boundary::Periodic);
+}
+
+#[test]
+fn divergence_is_negative_adjoint_of_gradient() {
+    for (h, w) in [(6, 11), (1, 5), (2, 2)] {
+        let u = Array2::from_shape_vec((h, w), pseudo_random(h * w, 1)).unwrap();
+        let p = Array3::from_shape_vec((h, w, 2), pseudo_random(h * w * 2, 2)).unwrap();
+        for boundary in Boundary::ALL {
+            let lhs = (gradient(&u.view(), boundary) * &p).sum();
+            let rhs = -(divergence(&p.view(), boundary) * &u).sum();
+            assert!((lhs - rhs).abs() < 1e-4, "{:?} {}x{}: {} != {}", boundary, h, w, lhs, rhs);
+        }
+    }
+}
+
+#[test]
+fn sym_divergence_is_negative_adjoint_of_sym_gradient() {
+    for (h, w) in [(9, 5), (1, 3), (2, 2)] {
+        let v = Array3::from_shape_vec((h, w, 2), pseudo_random(h * w * 2, 3)).unwrap();
+        let q = Array3::from_shape_vec((h, w, 3), pseudo_random(h * w * 3, 4)).unwrap();
+        for boundary in Boundary::ALL {
+            let lhs = (sym_gradient(&v.view(), boundary) * &q).sum();
+            let rhs = -(sym_divergence(&q.view(), boundary) * &v).sum();
+            assert!((lhs - rhs).abs() < 1e-4, "{:?} {}x{}: {} != {}", boundary, h, w, lhs, rhs);
+        }
+    }
+}
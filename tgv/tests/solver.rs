@@ -0,0 +1,269 @@
+use std::ops::ControlFlow;
+
+use ndarray::{Array2, Array3};
+use tgv::{
+    denoise_with_regularizer, tgv_denoise, tgv_denoise_color, tgv_denoise_color_with_report, tgv_restore_color_with_report,
+    vtgv_denoise, vtgv_denoise_with_report, ColorMode, DataTerm, Degradation, Regularization, TgvError, TgvParams, Tgv,
+};
+
+fn noisy_ramp(h: usize, w: usize) -> Array2<f32> {
+    // Affine ramp plus a deterministic +-10 checkerboard "noise"
+    Array2::from_shape_fn((h, w), |(i, j)| {
+        let noise = if (i + j) % 2 == 0 { 10. } else { -10. };
+        2. * i as f32 + j as f32 + noise
+    })
+}
+
+#[test]
+fn builder_validates_parameters() {
+    assert!(TgvParams::builder().lambda(0.5).build().is_ok());
+    assert_eq!(
+        TgvParams::builder().alpha0(-1.).build(),
+        Err(TgvError::NotPositive { name: "alpha0", value: -1. })
+    );
+    assert!(matches!(
+        TgvParams::builder().tau(0.5).sigma(0.5).build(),
+        Err(TgvError::StepSizeTooLarge { .. })
+    ));
+}
+
+#[test]
+fn constant_image_is_unchanged() {
+    let u0 = Array2::<f32>::from_elem((16, 12), 42.);
+    let params = TgvParams::builder().n_iter(20).build().unwrap();
+    let u = tgv_denoise(&u0.view(), &params);
+    assert!(u.iter().all(|&x| (x - 42.).abs() < 1e-4));
+}
+
+#[test]
+fn denoising_reduces_checkerboard_noise() {
+    let u0 = noisy_ramp(24, 20);
+    let params = TgvParams::builder().lambda(10.).n_iter(200).build().unwrap();
+    let u = tgv_denoise(&u0.view(), &params);
+
+    // Compare the mean absolute deviation from the clean ramp, away from the borders
+    let clean = Array2::from_shape_fn((24, 20), |(i, j)| 2. * i as f32 + j as f32);
+    let interior = ndarray::s![4..20, 4..16];
+    let error_before = (&u0.slice(interior) - &clean.slice(interior)).mapv(f32::abs).mean().unwrap();
+    let error_after = (&u.slice(interior) - &clean.slice(interior)).mapv(f32::abs).mean().unwrap();
+    assert!(error_after < 0.5 * error_before, "{} vs {}", error_after, error_before);
+}
+
+#[test]
+fn vectorial_with_one_channel_matches_scalar() {
+    let u0 = noisy_ramp(10, 14);
+    let params = TgvParams::builder().lambda(3.).n_iter(30).build().unwrap();
+    let scalar = tgv_denoise(&u0.view(), &params);
+    let vectorial = vtgv_denoise(&u0.view().insert_axis(ndarray::Axis(2)), &params, |_| ControlFlow::Continue(()));
+    assert_eq!(scalar, vectorial.remove_axis(ndarray::Axis(2)));
+}
+
+#[test]
+fn color_modes_return_rgb_and_report_progress() {
+    let u0 = Array3::from_shape_fn((8, 6, 3), |(i, j, c)| (i * 10 + j + 50 * c) as f32);
+    let params = TgvParams::builder().n_iter(5).build().unwrap();
+    for mode in ColorMode::ALL {
+        let mut last = (0, 0);
+        let u = tgv_denoise_color(&u0.view(), mode, &params, |done, total| {
+            last = (done, total);
+            ControlFlow::Continue(())
+        }).unwrap();
+        assert_eq!(u.dim(), (8, 6, 3));
+        assert_eq!(last.0, last.1, "{:?} did not report completion", mode);
+    }
+}
+
+#[test]
+fn stops_early_once_tolerance_is_met() {
+    let u0 = noisy_ramp(24, 20).insert_axis(ndarray::Axis(2));
+    let params = TgvParams::builder().lambda(10.).n_iter(5000).tolerance(1e-2).compute_gap(true).build().unwrap();
+    let (_, report) = vtgv_denoise_with_report(&u0.view(), &params, |_| ControlFlow::Continue(()));
+
+    assert!(report.converged);
+    assert!(report.iterations < params.n_iter);
+    assert_eq!(report.iterations % params.check_interval, 0);
+    assert_eq!(report.history.len(), report.iterations / params.check_interval);
+    let first = report.history.first().unwrap();
+    let last = report.history.last().unwrap();
+    assert!(last.primal <= 1e-2 && last.dual <= 1e-2);
+    assert!(last.gap.unwrap().abs() < first.gap.unwrap().abs());
+}
+
+#[test]
+fn runs_all_iterations_without_tolerance() {
+    let u0 = noisy_ramp(10, 14).insert_axis(ndarray::Axis(2));
+    let params = TgvParams::builder().n_iter(25).build().unwrap();
+    let (_, report) = vtgv_denoise_with_report(&u0.view(), &params, |_| ControlFlow::Continue(()));
+
+    assert!(!report.converged);
+    assert_eq!(report.iterations, 25);
+    // Every 10 iterations, plus the last one
+    let checked: Vec<usize> = report.history.iter().map(|r| r.iteration).collect();
+    assert_eq!(checked, [10, 20, 25]);
+    assert!(report.history.iter().all(|r| r.gap.is_none()));
+}
+
+#[test]
+fn progress_callback_cancels_the_run() {
+    let u0 = Array3::from_shape_fn((8, 6, 3), |(i, j, c)| (i * 10 + j + 50 * c) as f32);
+    let params = TgvParams::builder().n_iter(50).build().unwrap();
+    let (u, report) = vtgv_denoise_with_report(&u0.view(), &params, |i| {
+        if i == 7 { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+    });
+    assert!(report.cancelled);
+    assert_eq!(report.iterations, 7);
+    assert_eq!(u.dim(), u0.dim());
+
+    for mode in ColorMode::ALL {
+        let mut calls = 0;
+        tgv_denoise_color(&u0.view(), mode, &params, |_, _| {
+            calls += 1;
+            if calls == 3 { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+        }).unwrap();
+        assert_eq!(calls, 3, "{:?} kept running after cancellation", mode);
+    }
+}
+
+#[test]
+fn last_snapshot_is_the_result() {
+    let u0 = Array3::from_shape_fn((8, 6, 3), |(i, j, c)| (i * 10 + j + 50 * c) as f32);
+    let params = TgvParams::builder().n_iter(5).build().unwrap();
+    for mode in ColorMode::ALL {
+        let mut snapshots = Vec::new();
+        let (u, _) = tgv_denoise_color_with_report(&u0.view(), mode, &params, |progress| {
+            snapshots.push(progress.snapshot());
+            ControlFlow::Continue(())
+        }).unwrap();
+        assert_eq!(snapshots.len(), if matches!(mode, ColorMode::PerChannel | ColorMode::YCbCr) { 15 } else { 5 });
+        assert_eq!(snapshots.last(), Some(&u), "{:?}", mode);
+    }
+}
+
+#[test]
+fn regularizers_denoise_and_tgv_avoids_staircasing() {
+    let u0 = noisy_ramp(24, 20);
+    let clean = Array2::from_shape_fn((24, 20), |(i, j)| 2. * i as f32 + j as f32);
+    let interior = ndarray::s![4..20, 4..16];
+    let error_before = (&u0.slice(interior) - &clean.slice(interior)).mapv(f32::abs).mean().unwrap();
+    let mut errors = Vec::new();
+    for regularization in Regularization::ALL {
+        let params = TgvParams::builder().lambda(10.).n_iter(300).regularization(regularization).build().unwrap();
+        let u = tgv_denoise(&u0.view(), &params);
+        let error = (&u.slice(interior) - &clean.slice(interior)).mapv(f32::abs).mean().unwrap();
+        assert!(error < 0.5 * error_before, "{:?}: {} vs {}", regularization, error, error_before);
+        errors.push(error);
+    }
+    // TV flattens the ramp into steps, TGV follows it
+    assert!(errors[2] < errors[0], "TGV {} vs TV {}", errors[2], errors[0]);
+}
+
+#[test]
+fn gap_closes_for_every_regularizer() {
+    let u0 = noisy_ramp(16, 12).insert_axis(ndarray::Axis(2));
+    for regularization in Regularization::ALL {
+        let params = TgvParams::builder().lambda(5.).n_iter(500).regularization(regularization).compute_gap(true).build().unwrap();
+        let (_, report) = vtgv_denoise_with_report(&u0.view(), &params, |_| ControlFlow::Continue(()));
+        let first = report.history.first().unwrap().gap.unwrap();
+        let last = report.history.last().unwrap().gap.unwrap();
+        assert!(last.abs() < 0.1 * first.abs(), "{:?}: {} vs {}", regularization, last, first);
+    }
+}
+
+#[test]
+fn custom_regularizer_matches_params() {
+    let u0 = noisy_ramp(10, 14).insert_axis(ndarray::Axis(2));
+    let params = TgvParams::builder().lambda(3.).n_iter(30).build().unwrap();
+    let mut tgv = Tgv::new(u0.dim(), &params);
+    let (custom, _) = denoise_with_regularizer(&u0.view(), &params, &mut tgv, |_| ControlFlow::Continue(()));
+    assert_eq!(custom, vtgv_denoise(&u0.view(), &params, |_| ControlFlow::Continue(())));
+    assert_eq!(
+        TgvParams::builder().regularization(Regularization::HuberTv).huber_epsilon(0.).build(),
+        Err(TgvError::NotPositive { name: "huber_epsilon", value: 0. })
+    );
+}
+
+#[test]
+fn l1_data_term_removes_impulse_noise() {
+    // Smooth ramp with 2 in 13 pixels knocked out to 0 or 255
+    let clean = Array2::from_shape_fn((24, 20), |(i, j)| 100. + 2. * i as f32 + j as f32);
+    let u0 = Array2::from_shape_fn((24, 20), |(i, j)| match (i * 20 + j) % 13 {
+        0 => 0.,
+        6 => 255.,
+        _ => clean[[i, j]],
+    });
+    let error = |data_term| {
+        // The outliers are far from the ramp, a larger primal step gets there sooner
+        let params = TgvParams::builder().lambda(1.).tau(0.5).sigma(0.16).n_iter(1000).data_term(data_term).build().unwrap();
+        let u = tgv_denoise(&u0.view(), &params);
+        (&u - &clean).mapv(f32::abs).mean().unwrap()
+    };
+    let (l1, l2) = (error(DataTerm::L1), error(DataTerm::L2));
+    assert!(l1 < 0.1, "L1 error {}", l1);
+    assert!(l1 < 0.2 * l2, "L1 {} vs L2 {}", l1, l2);
+}
+
+// Poisson distributed sample with the given mean, from a linear congruential
+// generator, so the test is deterministic
+fn poisson(mean: f32, state: &mut u64) -> f32 {
+    let mut uniform = || {
+        *state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (*state >> 40) as f32 / (1u64 << 24) as f32
+    };
+    let limit = (-mean).exp();
+    let (mut count, mut product) = (0., uniform());
+    while product > limit {
+        count += 1.;
+        product *= uniform();
+    }
+    count
+}
+
+#[test]
+fn kl_data_term_denoises_low_counts() {
+    // A dim ramp of 0.5 to 8 photons per pixel, with many zero counts
+    let clean = Array2::from_shape_fn((32, 32), |(i, j)| 0.5 + 0.25 * (i + j) as f32 / 2.);
+    let mut state = 1;
+    let u0 = clean.mapv(|mean| poisson(mean, &mut state)).insert_axis(ndarray::Axis(2));
+    assert!(u0.iter().any(|&count| count == 0.));
+
+    let params = TgvParams::builder().lambda(0.5).n_iter(500).data_term(DataTerm::Kl).compute_gap(true).build().unwrap();
+    let (u, report) = vtgv_denoise_with_report(&u0.view(), &params, |_| ControlFlow::Continue(()));
+    let u = u.remove_axis(ndarray::Axis(2));
+    assert!(u.iter().all(|&x| x >= 0.));
+
+    let error_before = (&u0.remove_axis(ndarray::Axis(2)) - &clean).mapv(f32::abs).mean().unwrap();
+    let error_after = (&u - &clean).mapv(f32::abs).mean().unwrap();
+    assert!(error_after < 0.5 * error_before, "{} vs {}", error_after, error_before);
+    let gap = report.history.last().unwrap().gap.unwrap();
+    assert!(gap.is_finite() && gap.abs() < 1e-2, "gap {}", gap);
+}
+
+#[test]
+fn kl_data_term_keeps_colors_and_rejects_ycbcr() {
+    // The signed chroma channels of YCbCr are no photon counts
+    let params = TgvParams::builder().n_iter(20).data_term(DataTerm::Kl).build().unwrap();
+    assert_eq!(
+        ColorMode::YCbCr.validate(&params),
+        Err(TgvError::UnsupportedColorMode { data_term: DataTerm::Kl, mode: ColorMode::YCbCr })
+    );
+    let flat = Array3::from_shape_fn((6, 8, 3), |(_, _, c)| [40., 60., 160.][c]);
+    for mode in [ColorMode::Grayscale, ColorMode::Vectorial, ColorMode::PerChannel] {
+        assert_eq!(mode.validate(&params), Ok(()));
+        let u = tgv_denoise_color(&flat.view(), mode, &params, |_, _| ControlFlow::Continue(())).unwrap();
+        let expected = if mode == ColorMode::Grayscale { Array3::from_elem(flat.dim(), 260. / 3.) } else { flat.clone() };
+        assert!((&u - &expected).iter().all(|e| e.abs() < 1e-3), "{:?}", mode);
+    }
+    let l2 = TgvParams { data_term: DataTerm::L2, ..params };
+    assert_eq!(ColorMode::YCbCr.validate(&l2), Ok(()));
+}
+
+#[test]
+fn color_denoising_fails_for_kl_in_ycbcr() {
+    let params = TgvParams::builder().n_iter(1).data_term(DataTerm::Kl).build().unwrap();
+    let u0 = Array3::from_elem((4, 4, 3), 10.);
+    let expected = TgvError::UnsupportedColorMode { data_term: DataTerm::Kl, mode: ColorMode::YCbCr };
+    let denoised = tgv_denoise_color(&u0.view(), ColorMode::YCbCr, &params, |_, _| ControlFlow::Continue(()));
+    assert_eq!(denoised, Err(expected.clone()));
+    let restored = tgv_restore_color_with_report(&u0.view(), &Degradation::default(), ColorMode::YCbCr, &params, |_| ControlFlow::Continue(()));
+    assert_eq!(restored.err(), Some(expected));
+}
@@ -0,0 +1,445 @@
+// Batch TGV denoising of image files, e.g.
+//
+//     tgv-denoise "photos/*.jpg" --lambda 2.0 --output-dir denoised --format png
+//
+// Files are processed in parallel on the rayon thread pool.
+
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use clap::{Parser, ValueEnum};
+use image::ImageFormat;
+use ndarray::{Array2, Axis};
+use rayon::prelude::*;
+use tgv::{
+    array_to_image, image_to_array, parallel_tgv_denoise_color, tgv_denoise_color, tgv_restore_color_with_report, BitDepth,
+    Blend, Boundary, ColorMode, DataTerm, Degradation, Kernel, Regularization, TgvParams, TileConfig,
+};
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum ColorArg {
+    Grayscale,
+    Vectorial,
+    PerChannel,
+    Ycbcr,
+}
+
+impl From<ColorArg> for ColorMode {
+    fn from(arg: ColorArg) -> Self {
+        match arg {
+            ColorArg::Grayscale => ColorMode::Grayscale,
+            ColorArg::Vectorial => ColorMode::Vectorial,
+            ColorArg::PerChannel => ColorMode::PerChannel,
+            ColorArg::Ycbcr => ColorMode::YCbCr,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum BoundaryArg {
+    Periodic,
+    Neumann,
+    Symmetric,
+}
+
+impl From<BoundaryArg> for Boundary {
+    fn from(arg: BoundaryArg) -> Self {
+        match arg {
+            BoundaryArg::Periodic => Boundary::Periodic,
+            BoundaryArg::Neumann => Boundary::Neumann,
+            BoundaryArg::Symmetric => Boundary::Symmetric,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum RegularizerArg {
+    Tv,
+    HuberTv,
+    Tgv,
+}
+
+impl From<RegularizerArg> for Regularization {
+    fn from(arg: RegularizerArg) -> Self {
+        match arg {
+            RegularizerArg::Tv => Regularization::Tv,
+            RegularizerArg::HuberTv => Regularization::HuberTv,
+            RegularizerArg::Tgv => Regularization::Tgv,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum DataTermArg {
+    L2,
+    L1,
+    Kl,
+}
+
+impl From<DataTermArg> for DataTerm {
+    fn from(arg: DataTermArg) -> Self {
+        match arg {
+            DataTermArg::L2 => DataTerm::L2,
+            DataTermArg::L1 => DataTerm::L1,
+            DataTermArg::Kl => DataTerm::Kl,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum BlendArg {
+    Discard,
+    Feather,
+}
+
+impl From<BlendArg> for Blend {
+    fn from(arg: BlendArg) -> Self {
+        match arg {
+            BlendArg::Discard => Blend::Discard,
+            BlendArg::Feather => Blend::Feather,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum FormatArg {
+    Png,
+    Tiff,
+    Jpeg,
+    Bmp,
+    Webp,
+}
+
+impl FormatArg {
+    fn image_format(self) -> ImageFormat {
+        match self {
+            FormatArg::Png => ImageFormat::Png,
+            FormatArg::Tiff => ImageFormat::Tiff,
+            FormatArg::Jpeg => ImageFormat::Jpeg,
+            FormatArg::Bmp => ImageFormat::Bmp,
+            FormatArg::Webp => ImageFormat::WebP,
+        }
+    }
+
+    // The input depth if the format can store it, else the closest it can
+    fn bit_depth(self, input_depth: BitDepth) -> BitDepth {
+        match (self, input_depth) {
+            (FormatArg::Tiff, depth) => depth,
+            (FormatArg::Png, BitDepth::Float) => BitDepth::Sixteen,
+            (FormatArg::Png, depth) => depth,
+            _ => BitDepth::Eight,
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Denoise images with total generalized variation (TGV)")]
+struct Args {
+    /// Input files or glob patterns (quote patterns to keep the shell from expanding them)
+    #[arg(required = true)]
+    inputs: Vec<String>,
+
+    /// Directory the denoised images are written to
+    #[arg(short, long)]
+    output_dir: PathBuf,
+
+    /// Output image format. Images are written at the bit depth of the input where the
+    /// format supports it
+    #[arg(short, long, value_enum, default_value = "png")]
+    format: FormatArg,
+
+    /// Regularization weight, larger values denoise more
+    #[arg(short, long, default_value_t = TgvParams::default().lambda)]
+    lambda: f32,
+
+    /// Weight of the second order term
+    #[arg(long, default_value_t = TgvParams::default().alpha0)]
+    alpha0: f32,
+
+    /// Weight of the first order term
+    #[arg(long, default_value_t = TgvParams::default().alpha1)]
+    alpha1: f32,
+
+    /// Primal step size
+    #[arg(long, default_value_t = TgvParams::default().tau)]
+    tau: f32,
+
+    /// Dual step size
+    #[arg(long, default_value_t = TgvParams::default().sigma)]
+    sigma: f32,
+
+    /// Number of primal-dual iterations
+    #[arg(short = 'n', long, default_value_t = TgvParams::default().n_iter)]
+    iterations: usize,
+
+    /// Regularizer of the solver, the TV variants only use --alpha1
+    #[arg(short, long, value_enum, default_value = "tgv")]
+    regularizer: RegularizerArg,
+
+    /// Threshold below which the Huber TV regularizer is quadratic
+    #[arg(long, default_value_t = TgvParams::default().huber_epsilon)]
+    huber_epsilon: f32,
+
+    /// Data fidelity term, l1 for impulse (salt and pepper) noise, kl for Poisson
+    /// noise, see --gain
+    #[arg(short, long, value_enum, default_value = "l2")]
+    data_term: DataTermArg,
+
+    /// Photon counts per sample value of the inputs, for the kl data term. Samples
+    /// are taken at their own bit depth, a 16-bit sample of 1000 with a gain of 1 is
+    /// 1000 counts
+    #[arg(long, default_value_t = TgvParams::default().count_scale)]
+    gain: f32,
+
+    /// Stop early once the solver residuals are below this value
+    #[arg(long)]
+    tolerance: Option<f32>,
+
+    /// Boundary handling of the finite differences
+    #[arg(short, long, value_enum, default_value = "periodic")]
+    boundary: BoundaryArg,
+
+    /// How the channels of color images are denoised
+    #[arg(short, long, value_enum, default_value = "vectorial")]
+    color_mode: ColorArg,
+
+    /// Also remove a Gaussian blur with this standard deviation, in pixels
+    #[arg(long, group = "blur")]
+    blur_sigma: Option<f32>,
+
+    /// Also remove a linear motion blur over this many pixels
+    #[arg(long, group = "blur")]
+    motion_length: Option<f32>,
+
+    /// Direction of the motion blur in degrees, counterclockwise from the x axis
+    #[arg(long, default_value_t = 0.0, requires = "motion_length")]
+    motion_angle: f32,
+
+    /// Also remove the blur given by this point spread function image
+    #[arg(long, group = "blur")]
+    psf: Option<PathBuf>,
+
+    /// Fill in the pixels that are white in this mask image, e.g. scratches or dead
+    /// pixels, instead of denoising them. The mask must have the size of the inputs
+    #[arg(long)]
+    mask: Option<PathBuf>,
+
+    /// Also split each image into tiles that are denoised in parallel. Not
+    /// available when deblurring or inpainting
+    #[arg(long, conflicts_with_all = ["blur", "mask"])]
+    tiled: bool,
+
+    /// Tile size in pixels, with --tiled
+    #[arg(long, default_value_t = TileConfig::default().tile_size)]
+    tile_size: usize,
+
+    /// Halo around each tile in pixels, with --tiled
+    #[arg(long, default_value_t = TileConfig::default().overlap)]
+    overlap: usize,
+
+    /// How tile halos are stitched, with --tiled
+    #[arg(long, value_enum, default_value = "feather")]
+    blend: BlendArg,
+}
+
+impl Args {
+    // The blur to remove, if any
+    fn kernel(&self) -> Result<Option<Kernel>, String> {
+        let kernel = if let Some(sigma) = self.blur_sigma {
+            Kernel::gaussian(sigma)
+        } else if let Some(length) = self.motion_length {
+            Kernel::motion(length, self.motion_angle)
+        } else if let Some(psf) = &self.psf {
+            let img = image::open(psf)
+                .map_err(|e| format!("Failed to decode {}: {}", psf.display(), e))?;
+            let (psf, _) = image_to_array(&img);
+            Kernel::new(&psf.mean_axis(Axis(2)).unwrap().view())
+        } else {
+            return Ok(None);
+        };
+        kernel.map(Some).map_err(|e| e.to_string())
+    }
+
+    // Pixels to fill in, if any
+    fn missing(&self) -> Result<Option<Array2<bool>>, String> {
+        let Some(mask) = &self.mask else {
+            return Ok(None);
+        };
+        let img = image::open(mask)
+            .map_err(|e| format!("Failed to decode {}: {}", mask.display(), e))?
+            .into_luma8();
+        let (width, height) = img.dimensions();
+        Ok(Some(Array2::from_shape_fn((height as usize, width as usize), |(i, j)| img.get_pixel(j as u32, i as u32)[0] > 127)))
+    }
+
+    fn tile_config(&self) -> TileConfig {
+        TileConfig {
+            tile_size: self.tile_size,
+            overlap: self.overlap,
+            blend: self.blend.into(),
+        }
+    }
+}
+
+// Expands the glob patterns, keeping the order in which they were given
+fn expand_inputs(patterns: &[String]) -> Result<Vec<PathBuf>, String> {
+    let mut paths = Vec::new();
+    for pattern in patterns {
+        let matches = glob::glob(pattern)
+            .map_err(|e| format!("Invalid pattern {:?}: {}", pattern, e))?;
+        let before = paths.len();
+        for entry in matches {
+            let path = entry.map_err(|e| format!("Failed to read {:?}: {}", pattern, e))?;
+            if path.is_file() {
+                paths.push(path);
+            }
+        }
+        if paths.len() == before {
+            return Err(format!("No files match {:?}", pattern));
+        }
+    }
+    Ok(paths)
+}
+
+fn output_path(input: &Path, output_dir: &Path, format: ImageFormat) -> PathBuf {
+    let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = format.extensions_str()[0];
+    output_dir.join(format!("{}_tgv.{}", stem, extension))
+}
+
+// Fails if two inputs would be written to the same output, as they only differ by
+// their directory or extension
+fn check_outputs(inputs: &[PathBuf], output_dir: &Path, format: ImageFormat) -> Result<(), String> {
+    let mut outputs = HashMap::<PathBuf, &PathBuf>::new();
+    for input in inputs {
+        let output = output_path(input, output_dir, format);
+        if let Some(other) = outputs.get(&output) {
+            return Err(format!("{} and {} would both be written to {}", other.display(), input.display(), output.display()));
+        }
+        outputs.insert(output, input);
+    }
+    Ok(())
+}
+
+fn denoise_file(input: &Path, args: &Args, params: &TgvParams, degradation: &Degradation) -> Result<PathBuf, String> {
+    let img = image::open(input)
+        .map_err(|e| format!("Failed to decode image: {}", e))?;
+    let (img, depth) = image_to_array(&img);
+    // The arrays are on the 0..255 scale, not the scale of the samples
+    let params = &TgvParams { count_scale: params.count_scale * depth.counts_per_unit(), ..*params };
+    let (height, width, _) = img.dim();
+    if let Some(missing) = degradation.missing
+        && missing.dim() != (height, width)
+    {
+        let (mask_height, mask_width) = missing.dim();
+        return Err(format!("The mask is {}x{} pixels, the image {}x{}", mask_width, mask_height, width, height));
+    }
+
+    let color_mode = ColorMode::from(args.color_mode);
+    let denoised = if degradation.blur.is_some() || degradation.missing.is_some() {
+        tgv_restore_color_with_report(&img.view(), degradation, color_mode, params, |_| ControlFlow::Continue(())).map(|(denoised, _)| denoised)
+    } else if args.tiled {
+        parallel_tgv_denoise_color(&img.view(), color_mode, params, &args.tile_config())
+    } else {
+        tgv_denoise_color(&img.view(), color_mode, params, |_, _| ControlFlow::Continue(()))
+    };
+    let denoised = denoised.map_err(|e| e.to_string())?;
+
+    let denoised = array_to_image(&denoised.view(), args.format.bit_depth(depth));
+    let format = args.format.image_format();
+    let output = output_path(input, &args.output_dir, format);
+    denoised.save_with_format(&output, format)
+        .map_err(|e| format!("Failed to write {}: {}", output.display(), e))?;
+    Ok(output)
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    let mut params = TgvParams::builder()
+        .lambda(args.lambda)
+        .alpha0(args.alpha0)
+        .alpha1(args.alpha1)
+        .tau(args.tau)
+        .sigma(args.sigma)
+        .n_iter(args.iterations)
+        .boundary(args.boundary.into())
+        .regularization(args.regularizer.into())
+        .huber_epsilon(args.huber_epsilon)
+        .data_term(args.data_term.into())
+        .count_scale(args.gain);
+    if let Some(tolerance) = args.tolerance {
+        params = params.tolerance(tolerance);
+    }
+    let params = params.build();
+    let color_mode = ColorMode::from(args.color_mode);
+    let params = match params.and_then(|params| color_mode.validate(&params).map(|()| params)) {
+        Ok(params) => params,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let kernel = match args.kernel() {
+        Ok(kernel) => kernel,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if kernel.is_some() && let Err(e) = params.validate_for_deblurring() {
+        eprintln!("error: {}", e);
+        return ExitCode::FAILURE;
+    }
+    let missing = match args.missing() {
+        Ok(missing) => missing,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let degradation = Degradation { blur: kernel.as_ref(), missing: missing.as_ref().map(|missing| missing.view()) };
+    if args.tile_size == 0 {
+        eprintln!("error: --tile-size must be positive");
+        return ExitCode::FAILURE;
+    }
+
+    let inputs = match expand_inputs(&args.inputs) {
+        Ok(inputs) => inputs,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Err(e) = check_outputs(&inputs, &args.output_dir, args.format.image_format()) {
+        eprintln!("error: {}", e);
+        return ExitCode::FAILURE;
+    }
+    if let Err(e) = std::fs::create_dir_all(&args.output_dir) {
+        eprintln!("error: failed to create {}: {}", args.output_dir.display(), e);
+        return ExitCode::FAILURE;
+    }
+
+    let failures = inputs
+        .par_iter()
+        .map(|input| match denoise_file(input, &args, &params, &degradation) {
+            Ok(output) => {
+                println!("{} -> {}", input.display(), output.display());
+                0
+            }
+            Err(e) => {
+                eprintln!("error: {}: {}", input.display(), e);
+                1
+            }
+        })
+        .sum::<usize>();
+
+    if failures > 0 {
+        eprintln!("{} of {} files failed", failures, inputs.len());
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}